@@ -1,4 +1,5 @@
-use crate::dispatcher::Subscriber;
+use crate::dispatcher::{Policy, Subscriber, Tasks};
+use crate::dispatcher::config::{Config, Setting};
 use tokio::sync::mpsc;
 use telegram_bot::{Message, MessageKind};
 use crate::dispatcher::types;
@@ -10,17 +11,17 @@ pub struct Antimoon {
 }
 
 impl Antimoon {
-    pub fn new(api: telegram_bot::Api) -> Antimoon {
+    pub fn new(api: telegram_bot::Api, tasks: &Tasks, config: Config) -> Antimoon {
         let (send, recv) = mpsc::channel(1024);
-        tokio::spawn(Antimoon::main_loop(api, recv));
+        tasks.spawn(Antimoon::main_loop(api, recv, config));
         Antimoon {
             messages: send,
         }
     }
 
-    async fn main_loop(api: telegram_bot::Api, mut ch: mpsc::Receiver<Message>) {
+    async fn main_loop(api: telegram_bot::Api, mut ch: mpsc::Receiver<Message>, config: Config) {
         while let Some(Message {id, chat, kind: MessageKind::Text{data, ..}, ..}) = ch.recv().await {
-            if data.contains("🌚") {
+            if config.get(chat.id(), "filter").as_list().iter().any(|w| data.contains(w.as_str())) {
                 api.send(telegram_bot::DeleteMessage::new(chat, id)).await.unwrap();
             }
         }
@@ -33,4 +34,12 @@ impl Subscriber for Antimoon {
         res.insert(types::MessageKind::Text, vec![self.messages.clone()]);
         res
     }
+
+    fn policy(&self) -> Policy {
+        Policy::DropOldest
+    }
+
+    fn settings() -> Vec<Setting> {
+        vec![Setting::list("filter", "antimoon.settings.filter", &["🌚"])]
+    }
 }
@@ -1,6 +1,8 @@
 use crate::dispatcher::types;
-use crate::dispatcher::Subscriber;
+use crate::dispatcher::{Policy, Subscriber, Tasks};
+use crate::dispatcher::config::{Config, Setting};
 use crate::utils::must_send;
+use crate::i18n::{self, tr};
 use std::collections::HashMap;
 use std::vec::Vec;
 use rand::Rng;
@@ -24,10 +26,10 @@ pub struct Captcha {
 }
 
 impl Captcha {
-    pub fn new(api: telegram_bot::Api) -> Captcha {
+    pub fn new(api: telegram_bot::Api, tasks: &Tasks, config: Config) -> Captcha {
         let (send, recv) = mpsc::channel(1024);
         let (upd_send, upd_recv) = mpsc::channel(1024);
-        tokio::spawn(Captcha::main_loop(api, recv, upd_recv));
+        tasks.spawn(Captcha::main_loop(api, recv, upd_recv, config));
         Captcha {
             messages: send,
             updates: upd_send,
@@ -38,6 +40,7 @@ impl Captcha {
         api: telegram_bot::Api,
         mut ch: mpsc::Receiver<Message>,
         mut upd_ch: mpsc::Receiver<UpdateKind>,
+        config: Config,
     ) {
         let mut rng = rand::rngs::OsRng::default();
         let mut pending = HashMap::<MessageId, UserCaptcha>::new();
@@ -84,7 +87,8 @@ impl Captcha {
                 _ = timer.tick() => {
                     let mut removed = vec!();
                     for (mid, task) in &pending {
-                        if task.date.elapsed() > std::time::Duration::from_secs(60) {
+                        let timeout = config.get(task.chat_id, "timeout").as_integer() as u64;
+                        if task.date.elapsed() > std::time::Duration::from_secs(timeout) {
                             let kick = telegram_bot::KickChatMember::new(task.chat_id.clone(), task.user_id);                                                                      
                             must_send(&api, kick).await;
                             removed.push(mid.clone());
@@ -101,13 +105,13 @@ impl Captcha {
                         for user in data {
                             let restrict_msg = telegram_bot::RestrictChatMember::new(chat.clone(), user.id, deny_permissions.clone());
                             if must_send(&api, restrict_msg).await.is_none() {
-                                must_send(&api, telegram_bot::SendMessage::new(chat.clone(), "Когда мне тяжело, я всегда напоминаю себе о том, что если я сдамся – лучше не станет. А потом перестаю пытаться.")).await;
+                                must_send(&api, telegram_bot::SendMessage::new(chat.clone(), tr(i18n::lang(chat.id()), "captcha.no_rights", &[]))).await;
                                 continue;
                             };
                             
                             let task = (rng.gen::<u8>() % 9) + 1;
 
-                            let mut msg = telegram_bot::SendMessage::new(chat.clone(), format!("Слыш. Нажми кнопку {}, а то ебло откушу", task));
+                            let mut msg = telegram_bot::SendMessage::new(chat.clone(), tr(i18n::lang(chat.id()), "captcha.task", &[("n", (task as i64).into())]));
                             msg.reply_markup(keyboard.clone());
 
                             if let Some(MessageOrChannelPost::Message(resp)) = must_send(&api, msg).await {
@@ -160,4 +164,13 @@ impl Subscriber for Captcha {
         res.insert(types::UpdateKind::CallbackQuery, vec![self.updates.clone()]);
         res
     }
+
+    /// A dropped join would let the user in without a captcha
+    fn policy(&self) -> Policy {
+        Policy::Block
+    }
+
+    fn settings() -> Vec<Setting> {
+        vec![Setting::integer("timeout", "captcha.settings.timeout", 60, 20, 3600, 20)]
+    }
 }
@@ -0,0 +1,351 @@
+use crate::i18n::{self, Lang, tr};
+use crate::dispatcher::permissions::Permissions;
+use crate::utils::must_send;
+use serde::Serialize;
+use telegram_bot::types::{HttpRequest, JsonRequestType, JsonTrueToUnitResponse, RequestType, RequestUrl};
+use telegram_bot::{Api, Message, MessageEntity, MessageEntityKind, Request, SendMessage, UserId};
+use tokio::sync::mpsc::Sender;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    /// Single word
+    Word,
+    /// Signed integer
+    Integer,
+    /// `@username` or a mention of a user without one
+    User,
+    /// The rest of the message
+    Text,
+    /// One of the listed words
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Arity {
+    One,
+    Optional,
+    /// Zero or more, only as the last parameter
+    Many,
+}
+
+#[derive(Clone, Debug)]
+struct Param {
+    name: &'static str,
+    kind: ArgKind,
+    arity: Arity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    Anyone,
+    /// Chat admins and bot owners
+    Admin,
+    /// Users granted the role in the chat, chat admins and bot owners
+    Role(&'static str),
+    /// Bot owners only, for data shared by all chats
+    Owner,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserRef {
+    Id(UserId),
+    Username(String),
+}
+
+#[derive(Clone, Debug)]
+pub enum Arg {
+    Word(String),
+    Integer(i64),
+    User(UserRef),
+    Text(String),
+}
+
+/// Command syntax. Subcommands are declared as separate commands named like `quiz tidy`.
+#[derive(Clone, Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// i18n key of the description
+    pub description: &'static str,
+    pub permission: Permission,
+    params: Vec<Param>,
+}
+
+impl CommandSpec {
+    pub fn new(name: &'static str, description: &'static str) -> CommandSpec {
+        CommandSpec { name, description, permission: Permission::Anyone, params: vec!() }
+    }
+
+    pub fn admin(mut self) -> Self {
+        self.permission = Permission::Admin;
+        self
+    }
+
+    pub fn role(mut self, role: &'static str) -> Self {
+        self.permission = Permission::Role(role);
+        self
+    }
+
+    pub fn owner(mut self) -> Self {
+        self.permission = Permission::Owner;
+        self
+    }
+
+    pub fn arg(mut self, name: &'static str, kind: ArgKind) -> Self {
+        self.params.push(Param { name, kind, arity: Arity::One });
+        self
+    }
+
+    pub fn optional(mut self, name: &'static str, kind: ArgKind) -> Self {
+        self.params.push(Param { name, kind, arity: Arity::Optional });
+        self
+    }
+
+    pub fn many(mut self, name: &'static str, kind: ArgKind) -> Self {
+        self.params.push(Param { name, kind, arity: Arity::Many });
+        self
+    }
+
+    pub fn handled_by(self, handler: Sender<Invocation>) -> Command {
+        Command { spec: self, handler }
+    }
+
+    /// `/name <required> [optional] [many...]`
+    pub fn usage(&self) -> String {
+        let mut res = format!("/{}", self.name);
+        for param in &self.params {
+            let name = match param.kind {
+                ArgKind::Choice(choices) => choices.join("|"),
+                _ => param.name.to_string(),
+            };
+            res += &match param.arity {
+                Arity::One => format!(" <{}>", name),
+                Arity::Optional => format!(" [{}]", name),
+                Arity::Many => format!(" [{}...]", name),
+            };
+        }
+        res
+    }
+
+    fn path(&self) -> impl Iterator<Item = &'static str> {
+        self.name.split(' ')
+    }
+}
+
+/// Command declared by a module together with the channel receiving its invocations
+#[derive(Clone)]
+pub struct Command {
+    pub spec: CommandSpec,
+    pub(crate) handler: Sender<Invocation>,
+}
+
+/// Command with validated arguments
+#[derive(Clone, Debug)]
+pub struct Invocation {
+    pub command: &'static str,
+    pub message: Message,
+    /// Whether the sender is a chat admin or a bot owner
+    pub is_admin: bool,
+    args: Vec<(&'static str, Arg)>,
+}
+
+impl Invocation {
+    pub fn word(&self, name: &str) -> Option<&str> {
+        self.args.iter().find_map(|(n, a)| match a {
+            Arg::Word(w) if *n == name => Some(w.as_str()),
+            _ => None
+        })
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        self.args.iter().find_map(|(n, a)| match a {
+            Arg::Integer(i) if *n == name => Some(*i),
+            _ => None
+        })
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.args.iter().find_map(|(n, a)| match a {
+            Arg::Text(t) if *n == name => Some(t.as_str()),
+            _ => None
+        })
+    }
+
+    pub fn users(&self, name: &str) -> Vec<&UserRef> {
+        self.args.iter().filter_map(|(n, a)| match a {
+            Arg::User(u) if *n == name => Some(u),
+            _ => None
+        }).collect()
+    }
+}
+
+/// Whitespace separated words of a command message with their byte offsets
+pub(crate) struct Call<'a> {
+    text: &'a str,
+    entities: &'a [MessageEntity],
+    words: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Call<'a> {
+    /// `None` unless the text is a command without a bot name or addressed to `bot`
+    pub fn parse(text: &'a str, entities: &'a [MessageEntity], bot: &str) -> Option<Call<'a>> {
+        let mut words = vec!();
+        let mut start = None;
+        for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    words.push((s, &text[s..i]));
+                    start = None;
+                }
+                (false, None) => start = Some(i),
+                _ => {}
+            }
+        }
+        let (offset, first) = *words.first()?;
+        let command = first.strip_prefix('/')?;
+        let command = match command.split_once('@') {
+            Some((command, target)) if target.eq_ignore_ascii_case(bot) => command,
+            Some(_) => return None,
+            None => command,
+        };
+        if command.is_empty() {
+            return None
+        }
+        words[0] = (offset + 1, command);
+        Some(Call { text, entities, words })
+    }
+
+    /// Spec with the longest name matching the call, so `/quiz tidy on` prefers `quiz tidy` over `quiz`
+    pub fn find<'s, I: IntoIterator<Item = &'s CommandSpec>>(&self, specs: I) -> Option<&'s CommandSpec> {
+        specs.into_iter()
+            .filter(|spec| {
+                let path = spec.path().collect::<Vec<&str>>();
+                path.len() <= self.words.len() && path.iter().zip(self.words.iter()).all(|(p, w)| *p == w.1)
+            })
+            .max_by_key(|spec| spec.path().count())
+    }
+
+    fn utf16_offset(&self, byte: usize) -> i64 {
+        self.text[..byte].encode_utf16().count() as i64
+    }
+
+    /// Parses one value starting at the word `i`, returns it with the number of words it takes
+    fn value(&self, i: usize, kind: ArgKind) -> Option<(Arg, usize)> {
+        let (start, word) = self.words[i];
+        match kind {
+            ArgKind::Word => Some((Arg::Word(word.to_string()), 1)),
+            ArgKind::Integer => word.parse::<i64>().ok().map(|n| (Arg::Integer(n), 1)),
+            ArgKind::Choice(choices) if choices.contains(&word) => Some((Arg::Word(word.to_string()), 1)),
+            ArgKind::Choice(_) => None,
+            ArgKind::Text => Some((Arg::Text(self.text[start..].trim_end().to_string()), self.words.len() - i)),
+            ArgKind::User => {
+                let offset = self.utf16_offset(start);
+                let mention = self.entities.iter().find_map(|e| match &e.kind {
+                    MessageEntityKind::TextMention(user) if e.offset == offset => Some((user.id, offset + e.length)),
+                    _ => None
+                });
+                match mention {
+                    // A mention of a user without username is their name, which may be several words
+                    Some((id, end)) => {
+                        let len = self.words[i..].iter().take_while(|(s, _)| self.utf16_offset(*s) < end).count();
+                        Some((Arg::User(UserRef::Id(id)), len))
+                    }
+                    None => word.strip_prefix('@')
+                        .filter(|name| !name.is_empty())
+                        .map(|name| (Arg::User(UserRef::Username(name.to_string())), 1)),
+                }
+            }
+        }
+    }
+
+    /// Arguments of the call according to the spec, `None` if they don't match
+    pub fn args(&self, spec: &CommandSpec) -> Option<Vec<(&'static str, Arg)>> {
+        let mut i = spec.path().count();
+        let mut res = vec!();
+        for param in &spec.params {
+            match param.arity {
+                Arity::One | Arity::Optional if i < self.words.len() => {
+                    let (arg, len) = self.value(i, param.kind)?;
+                    res.push((param.name, arg));
+                    i += len;
+                }
+                Arity::One => return None,
+                Arity::Optional => {}
+                Arity::Many => while i < self.words.len() {
+                    let (arg, len) = self.value(i, param.kind)?;
+                    res.push((param.name, arg));
+                    i += len;
+                },
+            }
+        }
+        if i < self.words.len() {
+            return None
+        }
+        Some(res)
+    }
+}
+
+/// Checks the permission and arguments of the command, replying with its usage if the arguments don't match
+pub(crate) async fn invoke(api: &Api, permissions: &Permissions, call: &Call<'_>, spec: &CommandSpec, message: &Message) -> Option<Invocation> {
+    let chat = message.chat.id();
+    let is_admin = permissions.is_admin(&message.chat, message.from.id).await;
+    if !permissions.allows(chat, message.from.id, is_admin, spec.permission) {
+        return None
+    }
+    match call.args(spec) {
+        Some(args) => Some(Invocation { command: spec.name, message: message.clone(), is_admin, args }),
+        None => {
+            let text = tr(i18n::lang(chat), "commands.usage", &[("usage", (&spec.usage()).into())]);
+            must_send(api, SendMessage::new(chat, text)).await;
+            None
+        }
+    }
+}
+
+/// `/help` listing the given commands
+pub(crate) fn help<'s, I: IntoIterator<Item = &'s CommandSpec>>(lang: Lang, specs: I) -> String {
+    let mut res = tr(lang, "commands.help", &[]);
+    for spec in specs {
+        res += &format!("\n{} — {}", spec.usage(), tr(lang, spec.description, &[]));
+        res += &match spec.permission {
+            Permission::Anyone => String::new(),
+            Permission::Admin => tr(lang, "commands.admin_only", &[]),
+            Permission::Role(role) => tr(lang, "commands.role_only", &[("role", role.into())]),
+            Permission::Owner => tr(lang, "commands.owner_only", &[]),
+        };
+    }
+    res
+}
+
+#[derive(Serialize, Debug)]
+struct BotCommand {
+    command: String,
+    description: String,
+}
+
+/// Command list shown by Telegram clients, Bot API `setMyCommands`
+#[derive(Serialize, Debug)]
+pub(crate) struct SetMyCommands {
+    commands: Vec<BotCommand>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language_code: Option<&'static str>,
+}
+
+impl SetMyCommands {
+    /// Top level commands described in `lang`, registered for users with `language_code` or for everyone if it's `None`
+    pub fn new<'s, I: IntoIterator<Item = &'s CommandSpec>>(lang: Lang, language_code: Option<&'static str>, specs: I) -> SetMyCommands {
+        let commands = specs.into_iter()
+            .filter(|spec| !spec.name.contains(' '))
+            .map(|spec| BotCommand { command: spec.name.to_string(), description: tr(lang, spec.description, &[]) })
+            .collect();
+        SetMyCommands { commands, language_code }
+    }
+}
+
+impl Request for SetMyCommands {
+    type Type = JsonRequestType<Self>;
+    type Response = JsonTrueToUnitResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, telegram_bot::types::Error> {
+        <Self::Type as RequestType>::serialize(RequestUrl::method("setMyCommands"), self)
+    }
+}
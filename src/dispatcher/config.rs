@@ -0,0 +1,288 @@
+use rusqlite::*;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use telegram_bot::ChatId;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    DBError(rusqlite::Error),
+    UnknownSetting,
+    InvalidValue,
+    /// The setting was changed since the version the caller has seen
+    Conflict,
+}
+
+impl From<rusqlite::Error> for ConfigError {
+    fn from(e: Error) -> Self {
+        ConfigError::DBError(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Bool,
+    /// `step` is used by the buttons of the settings menu
+    Integer { min: i64, max: i64, step: i64 },
+    Choice(&'static [&'static str]),
+    /// Whitespace separated words
+    List,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    Word(String),
+    List(Vec<String>),
+}
+
+impl Value {
+    pub fn as_bool(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    pub fn as_integer(&self) -> i64 {
+        match self {
+            Value::Integer(n) => *n,
+            _ => 0
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Value::Word(w) => w,
+            _ => ""
+        }
+    }
+
+    pub fn as_list(&self) -> &[String] {
+        match self {
+            Value::List(l) => l,
+            _ => &[]
+        }
+    }
+
+    /// Text form, accepted back by `Kind::parse`
+    pub fn encode(&self) -> String {
+        match self {
+            Value::Bool(b) => if *b { "on" } else { "off" }.to_string(),
+            Value::Integer(n) => n.to_string(),
+            Value::Word(w) => w.clone(),
+            Value::List(l) => l.join(" "),
+        }
+    }
+}
+
+impl Kind {
+    pub fn parse(&self, text: &str) -> Option<Value> {
+        let text = text.trim();
+        match self {
+            Kind::Bool => match text {
+                "on" => Some(Value::Bool(true)),
+                "off" => Some(Value::Bool(false)),
+                _ => None
+            },
+            Kind::Integer { min, max, .. } => text.parse::<i64>().ok()
+                .filter(|n| n >= min && n <= max)
+                .map(Value::Integer),
+            Kind::Choice(choices) if choices.contains(&text) => Some(Value::Word(text.to_string())),
+            Kind::Choice(_) => None,
+            Kind::List => Some(Value::List(text.split_whitespace().map(String::from).collect())),
+        }
+    }
+
+    /// Accepted values, shown when parsing fails
+    pub fn describe(&self) -> String {
+        match self {
+            Kind::Bool => "on|off".to_string(),
+            Kind::Integer { min, max, .. } => format!("{}..{}", min, max),
+            Kind::Choice(choices) => choices.join("|"),
+            Kind::List => "word ...".to_string(),
+        }
+    }
+}
+
+/// Setting declared by a module, chats without a stored value get the default
+#[derive(Clone, Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    /// i18n key of the description
+    pub description: &'static str,
+    pub kind: Kind,
+    pub default: Value,
+}
+
+impl Setting {
+    pub fn bool(key: &'static str, description: &'static str, default: bool) -> Setting {
+        Setting { key, description, kind: Kind::Bool, default: Value::Bool(default) }
+    }
+
+    pub fn integer(key: &'static str, description: &'static str, default: i64, min: i64, max: i64, step: i64) -> Setting {
+        Setting { key, description, kind: Kind::Integer { min, max, step }, default: Value::Integer(default) }
+    }
+
+    pub fn choice(key: &'static str, description: &'static str, choices: &'static [&'static str], default: &'static str) -> Setting {
+        Setting { key, description, kind: Kind::Choice(choices), default: Value::Word(default.to_string()) }
+    }
+
+    pub fn list(key: &'static str, description: &'static str, default: &[&str]) -> Setting {
+        Setting { key, description, kind: Kind::List, default: Value::List(default.iter().map(|s| s.to_string()).collect()) }
+    }
+}
+
+/// Per-chat module settings. Every write bumps the version of the value, so stale edits can be detected.
+pub(crate) struct ConfigStore {
+    conn: std::sync::Mutex<Connection>,
+    schemas: RwLock<HashMap<String, Vec<Setting>>>,
+    /// Stored values by `(chat, module, key)` with their versions
+    values: RwLock<HashMap<(i64, String, String), (Value, i64)>>,
+    /// Rows loaded before their module declared the settings
+    raw: RwLock<HashMap<(i64, String, String), (String, i64)>>,
+}
+
+impl ConfigStore {
+    pub fn new<T: AsRef<std::path::Path>>(path: T) -> Result<ConfigStore, ConfigError> {
+        let db = Connection::open(path)?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS config
+                        (chat_id INTEGER, module TEXT, key TEXT, value TEXT, version INTEGER,
+                        PRIMARY KEY(chat_id, module, key))",
+            params![],
+        )?;
+        let raw = {
+            let mut query = db.prepare("SELECT chat_id, module, key, value, version FROM config")?;
+            let rows = query.query_map(NO_PARAMS, |x| {
+                Ok(((x.get(0)?, x.get(1)?, x.get(2)?), (x.get(3)?, x.get(4)?)))
+            })?;
+            rows.collect::<Result<HashMap<_, _>>>()?
+        };
+        Ok(ConfigStore {
+            conn: std::sync::Mutex::new(db),
+            schemas: RwLock::new(HashMap::new()),
+            values: RwLock::new(HashMap::new()),
+            raw: RwLock::new(raw),
+        })
+    }
+
+    /// Declares the settings of the module and decodes its stored values, invalid ones fall back to defaults
+    pub fn register(&self, module: &str, settings: Vec<Setting>) {
+        let mut values = self.values.write().unwrap();
+        self.raw.write().unwrap().retain(|(chat, m, key), (value, version)| {
+            if m.as_str() != module {
+                return true
+            }
+            match settings.iter().find(|s| s.key == key.as_str()).and_then(|s| s.kind.parse(value)) {
+                Some(value) => {
+                    values.insert((*chat, m.clone(), key.clone()), (value, *version));
+                }
+                None => println!("Config {}.{} of {}: ignoring {:?}", m, key, chat, value),
+            }
+            false
+        });
+        self.schemas.write().unwrap().insert(module.to_string(), settings);
+    }
+
+    /// Modules with settings, sorted by name
+    pub fn modules(&self) -> Vec<String> {
+        let mut res = self.schemas.read().unwrap().iter()
+            .filter(|(_, settings)| !settings.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>();
+        res.sort();
+        res
+    }
+
+    pub fn schema(&self, module: &str) -> Vec<Setting> {
+        self.schemas.read().unwrap().get(module).cloned().unwrap_or_default()
+    }
+
+    fn setting(&self, module: &str, key: &str) -> Option<Setting> {
+        self.schemas.read().unwrap().get(module)?.iter().find(|s| s.key == key).cloned()
+    }
+
+    /// Current value and its version, 0 if it was never set
+    pub fn get(&self, chat: ChatId, module: &str, key: &str) -> Result<(Value, i64), ConfigError> {
+        let setting = self.setting(module, key).ok_or(ConfigError::UnknownSetting)?;
+        let values = self.values.read().unwrap();
+        Ok(values.get(&(i64::from(chat), module.to_string(), key.to_string()))
+            .cloned()
+            .unwrap_or((setting.default, 0)))
+    }
+
+    /// Stores the value if `expected` is `None` or matches the current version, returns the new version
+    pub fn set(&self, chat: ChatId, module: &str, key: &str, value: Value, expected: Option<i64>) -> Result<i64, ConfigError> {
+        let setting = self.setting(module, key).ok_or(ConfigError::UnknownSetting)?;
+        if setting.kind.parse(&value.encode()).as_ref() != Some(&value) {
+            return Err(ConfigError::InvalidValue)
+        }
+        let id = (i64::from(chat), module.to_string(), key.to_string());
+        let mut values = self.values.write().unwrap();
+        let version = values.get(&id).map_or(0, |(_, version)| *version);
+        if expected.map_or(false, |expected| expected != version) {
+            return Err(ConfigError::Conflict)
+        }
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO config (chat_id, module, key, value, version) VALUES(?, ?, ?, ?, ?)",
+            params![id.0, id.1, id.2, value.encode(), version + 1],
+        )?;
+        values.insert(id, (value, version + 1));
+        Ok(version + 1)
+    }
+
+    /// Moves the stored settings of a group to its supergroup, settings the supergroup already has are kept.
+    /// The loaded values follow with `migrate_values` once the whole chat has moved.
+    pub fn migrate(&self, from: ChatId, to: ChatId) -> Result<(), ConfigError> {
+        let (from, to) = (i64::from(from), i64::from(to));
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("UPDATE OR IGNORE config SET chat_id=? WHERE chat_id=?", params![to, from])?;
+        tx.execute("DELETE FROM config WHERE chat_id=?", params![from])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn migrate_values(&self, from: ChatId, to: ChatId) {
+        let (from, to) = (i64::from(from), i64::from(to));
+        let mut values = self.values.write().unwrap();
+        let moved = values.keys().filter(|(chat, _, _)| *chat == from).cloned().collect::<Vec<_>>();
+        for id in moved {
+            let value = values.remove(&id).unwrap();
+            values.entry((to, id.1, id.2)).or_insert(value);
+        }
+        let mut raw = self.raw.write().unwrap();
+        let moved = raw.keys().filter(|(chat, _, _)| *chat == from).cloned().collect::<Vec<_>>();
+        for id in moved {
+            let value = raw.remove(&id).unwrap();
+            raw.entry((to, id.1, id.2)).or_insert(value);
+        }
+    }
+}
+
+/// Settings of one module, handed to the module on start
+#[derive(Clone)]
+pub struct Config {
+    store: Arc<ConfigStore>,
+    module: String,
+}
+
+impl Config {
+    pub(crate) fn new(store: Arc<ConfigStore>, module: &str) -> Config {
+        Config { store, module: module.to_string() }
+    }
+
+    /// Value of a declared setting, panics on undeclared ones
+    pub fn get(&self, chat: ChatId, key: &str) -> Value {
+        self.store.get(chat, &self.module, key).expect("setting is not declared").0
+    }
+
+    pub fn set(&self, chat: ChatId, key: &str, value: Value) -> Result<(), ConfigError> {
+        self.store.set(chat, &self.module, key, value, None).map(|_| ())
+    }
+
+    /// Parses and stores a value in its text form
+    pub fn set_text(&self, chat: ChatId, key: &str, text: &str) -> Result<(), ConfigError> {
+        let setting = self.store.setting(&self.module, key).ok_or(ConfigError::UnknownSetting)?;
+        let value = setting.kind.parse(text).ok_or(ConfigError::InvalidValue)?;
+        self.set(chat, key, value)
+    }
+}
@@ -0,0 +1,143 @@
+use crate::dispatcher::commands::Invocation;
+use crate::dispatcher::modules_menu;
+use crate::dispatcher::config::{ConfigError, ConfigStore, Kind, Value};
+use crate::dispatcher::permissions::Permissions;
+use crate::i18n::{self, Lang, tr};
+use crate::markdown::{MarkdownV2, Text};
+use crate::utils::{must_send, send_text};
+use telegram_bot::{Api, CallbackQuery, CanAnswerCallbackQuery, ChatId, EditMessageText, InlineKeyboardButton,
+                   InlineKeyboardMarkup, MessageOrChannelPost, SendMessage};
+
+/// Prefix of the callback data of settings menu buttons
+pub(crate) const CALLBACK_PREFIX: &str = "cfg:";
+
+/// Current settings of the module with buttons to change them. Buttons carry the version of the value
+/// they were rendered for, so pressing a stale button doesn't overwrite a newer change.
+pub(crate) fn menu(store: &ConfigStore, lang: Lang, chat: ChatId, module: &str) -> (Text<MarkdownV2>, InlineKeyboardMarkup) {
+    let mut text = Text::markdown();
+    text.text(&tr(lang, "config.title", &[("module", module.into())]));
+    let mut keyboard = InlineKeyboardMarkup::new();
+    for setting in store.schema(module) {
+        let (value, version) = store.get(chat, module, setting.key).unwrap();
+        let shown = value.encode();
+        text.text("\n").bold(setting.key).text(" = ")
+            .code(if shown.is_empty() { "-" } else { shown.as_str() })
+            .text(&format!(" — {}", tr(lang, setting.description, &[])));
+
+        let data = |op: &str| format!("{}{}:{}:{}:{}", CALLBACK_PREFIX, module, setting.key, version, op);
+        match setting.kind {
+            Kind::Bool | Kind::Choice(_) => {
+                keyboard.add_row(vec![InlineKeyboardButton::callback(format!("{}: {}", setting.key, shown), data("next"))]);
+            }
+            Kind::Integer { step, .. } => {
+                keyboard.add_row(vec![
+                    InlineKeyboardButton::callback(format!("{} −{}", setting.key, step), data("dec")),
+                    InlineKeyboardButton::callback(format!("{} +{}", setting.key, step), data("inc")),
+                ]);
+            }
+            Kind::List => {}
+        }
+    }
+    keyboard.add_row(vec![InlineKeyboardButton::callback(tr(lang, "modules.back", &[]), format!("{}list", modules_menu::CALLBACK_PREFIX))]);
+    (text, keyboard)
+}
+
+/// Value after pressing the button `op`
+fn next(kind: Kind, value: &Value, op: &str) -> Option<Value> {
+    match (kind, op) {
+        (Kind::Bool, "next") => Some(Value::Bool(!value.as_bool())),
+        (Kind::Choice(choices), "next") => {
+            let pos = choices.iter().position(|c| *c == value.as_str()).map_or(0, |p| p + 1);
+            Some(Value::Word(choices[pos % choices.len()].to_string()))
+        }
+        (Kind::Integer { min, step, .. }, "dec") => Some(Value::Integer(std::cmp::max(value.as_integer() - step, min))),
+        (Kind::Integer { max, step, .. }, "inc") => Some(Value::Integer(std::cmp::min(value.as_integer() + step, max))),
+        _ => None
+    }
+}
+
+/// `/config [module] [key] [value]`: lists modules with settings, shows the menu of a module, shows or changes a value
+pub(crate) async fn command(api: &Api, store: &ConfigStore, invocation: &Invocation) {
+    let chat = invocation.message.chat.id();
+    let lang = i18n::lang(chat);
+    let module = match invocation.word("module") {
+        Some(module) => module,
+        None => {
+            let text = tr(lang, "config.modules", &[("modules", (&store.modules().join(", ")).into())]);
+            must_send(api, SendMessage::new(chat, text)).await;
+            return
+        }
+    };
+    let schema = store.schema(module);
+    if schema.is_empty() {
+        must_send(api, SendMessage::new(chat, tr(lang, "config.unknown_module", &[]))).await;
+        return
+    }
+    let setting = match invocation.word("key") {
+        Some(key) => match schema.iter().find(|s| s.key == key) {
+            Some(setting) => setting,
+            None => {
+                let keys = schema.iter().map(|s| s.key).collect::<Vec<&str>>().join(", ");
+                must_send(api, SendMessage::new(chat, tr(lang, "config.unknown_key", &[("keys", (&keys).into())]))).await;
+                return
+            }
+        },
+        None => {
+            let (text, keyboard) = menu(store, lang, chat, module);
+            let mut msg = SendMessage::new(chat, text.as_str().to_string());
+            msg.parse_mode(text.parse_mode());
+            msg.reply_markup(keyboard);
+            must_send(api, msg).await;
+            return
+        }
+    };
+
+    if let Some(text) = invocation.text("value") {
+        let res = setting.kind.parse(text).ok_or(ConfigError::InvalidValue)
+            .and_then(|value| store.set(chat, module, setting.key, value, None));
+        if let Err(e) = res {
+            println!("Config {}.{} of {}: {:?}", module, setting.key, i64::from(chat), e);
+            let text = tr(lang, "config.invalid", &[("key", setting.key.into()), ("expected", (&setting.kind.describe()).into())]);
+            must_send(api, SendMessage::new(chat, text)).await;
+            return
+        }
+    }
+    let (value, _) = store.get(chat, module, setting.key).unwrap();
+    let mut text = Text::markdown();
+    text.bold(setting.key).text(" = ").code(&value.encode()).text(&format!("\n{}", tr(lang, setting.description, &[])));
+    send_text(api, chat, &text, None).await;
+}
+
+/// Handles a settings menu button, the menu is edited in place
+pub(crate) async fn callback(api: &Api, store: &ConfigStore, permissions: &Permissions, query: &CallbackQuery) {
+    must_send(api, query.acknowledge()).await;
+    let msg = match &query.message {
+        Some(MessageOrChannelPost::Message(msg)) => msg,
+        _ => return
+    };
+    if !permissions.is_admin(&msg.chat, query.from.id).await {
+        return
+    }
+    let data = query.data.as_deref().unwrap_or_default();
+    let parts = data.trim_start_matches(CALLBACK_PREFIX).split(':').collect::<Vec<&str>>();
+    let (module, key, version, op) = match parts.as_slice() {
+        [module, key, version, op] => (*module, *key, version.parse::<i64>().unwrap_or_default(), *op),
+        _ => return
+    };
+    let chat = msg.chat.id();
+    if let Some(setting) = store.schema(module).into_iter().find(|s| s.key == key) {
+        let (value, _) = store.get(chat, module, key).unwrap();
+        if let Some(value) = next(setting.kind, &value, op) {
+            // A conflict means someone else changed it first, the menu is refreshed with their value
+            if let Err(e) = store.set(chat, module, key, value, Some(version)) {
+                println!("Config {}.{} of {}: {:?}", module, key, i64::from(chat), e);
+            }
+        }
+    }
+
+    let (text, keyboard) = menu(store, i18n::lang(chat), chat, module);
+    let mut edit = EditMessageText::new(chat, msg.id, text.as_str().to_string());
+    edit.parse_mode(text.parse_mode());
+    edit.reply_markup(keyboard);
+    must_send(api, edit).await;
+}
@@ -1,50 +1,84 @@
+pub mod commands;
+pub mod config;
+mod config_menu;
 mod modules;
+mod modules_menu;
+mod permissions;
+mod queue;
+mod record;
+mod supervisor;
 pub mod types;
+mod webhook;
 use crate::dispatcher::types::{MessageKind, UpdateKind};
-use lazy_static::lazy_static;
-use std::collections::HashMap;
+use crate::dispatcher::commands::{Call, Command, CommandSpec, ArgKind, Permission, SetMyCommands, UserRef};
+use crate::dispatcher::config::{Config, ConfigError, ConfigStore, Setting};
+use crate::dispatcher::permissions::Permissions;
+use crate::dispatcher::supervisor::{Factory, Supervised};
+pub(crate) use crate::dispatcher::webhook::UpdateMode;
+use crate::fake_api::FakeApi;
+pub use crate::dispatcher::supervisor::Tasks;
+pub use crate::dispatcher::queue::Policy;
+use crate::i18n::{self, Lang, tr};
+use crate::markdown::{self, Mentions, Text};
+use crate::settings::Settings;
+use crate::users::{Users, UsersError};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use telegram_bot::{Api, ChatId, ChatMemberStatus, Message, SendMessage, Update, UserId};
-use tokio::stream::StreamExt;
-use tokio::sync::mpsc;
-
-lazy_static! {
-    static ref EMPTY_MODULELIST: HashMap<String, Module> = HashMap::new();
-}
+use std::sync::Arc;
+use telegram_bot::{Api, GetMe, Message, SendMessage, Update, UserId};
+use tokio::sync::{mpsc, Mutex};
 
 #[derive(Clone)]
-struct Module {
+pub(crate) struct Module {
     message_kind: HashMap<MessageKind, Vec<mpsc::Sender<Message>>>,
     update_kind: HashMap<UpdateKind, Vec<mpsc::Sender<telegram_bot::UpdateKind>>>,
-    command: HashMap<String, Vec<mpsc::Sender<Message>>>,
+    commands: Vec<Command>,
+    policy: Policy,
+}
+
+/// What modules need to know about the bot itself
+pub(crate) struct Context {
+    api: Api,
+    username: String,
+    permissions: Arc<Permissions>,
 }
 
 impl Module {
-    async fn handle(&mut self, update: &Update) -> Result<(), Box<dyn Error>> {
+    /// Delivers the update to every matching handler. A closed channel means the handler task is gone,
+    /// the first such error is returned after trying the rest.
+    async fn handle(&mut self, update: &Update, ctx: &Context) -> Result<(), Box<dyn Error>> {
+        let mut res: Result<(), Box<dyn Error>> = Ok(());
         let update_kind = UpdateKind::from(&update.kind);
         if let Some(e) = self.update_kind.get_mut(&update_kind) {
             for handler in e {
-                handler.send(update.kind.clone()).await?;
+                if let Err(e) = handler.send(update.kind.clone()).await {
+                    res = res.and(Err(e.into()));
+                }
             }
         };
         if let telegram_bot::UpdateKind::Message(msg) = &update.kind {
             let message_kind = MessageKind::from(&msg.kind);
             if let Some(e) = self.message_kind.get_mut(&message_kind) {
                 for handler in e {
-                    handler.send(msg.clone()).await?;
+                    if let Err(e) = handler.send(msg.clone()).await {
+                        res = res.and(Err(e.into()));
+                    }
                 }
             };
-            if let telegram_bot::MessageKind::Text { data, .. } = &msg.kind {
-                if let Some(command) = data.split(&[' ', '\n', '@'][..]).next() {
-                    if let Some(e) = self.command.get_mut(command) {
-                        for handler in e {
-                            handler.send(msg.clone()).await?;
+            if let telegram_bot::MessageKind::Text { data, entities } = &msg.kind {
+                if let Some(call) = Call::parse(data, entities, &ctx.username) {
+                    let spec = call.find(self.commands.iter().map(|c| &c.spec)).map(|spec| spec.name);
+                    if let Some(command) = self.commands.iter_mut().find(|c| Some(c.spec.name) == spec) {
+                        if let Some(invocation) = commands::invoke(&ctx.api, &ctx.permissions, &call, &command.spec, msg).await {
+                            if let Err(e) = command.handler.send(invocation).await {
+                                res = res.and(Err(e.into()));
+                            }
                         }
-                    };
+                    }
                 }
             }
         }
-        Ok(())
+        res
     }
 }
 
@@ -53,7 +87,8 @@ impl<T: Subscriber> From<&T> for Module {
         let mut res = Self {
             message_kind: HashMap::new(),
             update_kind: HashMap::new(),
-            command: HashMap::new(),
+            commands: sub.commands(),
+            policy: sub.policy(),
         };
         for (kind, mut senders) in sub.by_message_kind().into_iter() {
             let t = res.message_kind.get_mut(&kind);
@@ -73,15 +108,6 @@ impl<T: Subscriber> From<&T> for Module {
                 Some(t) => t.append(&mut senders),
             }
         }
-        for (kind, mut senders) in sub.by_command().into_iter() {
-            let t = res.command.get_mut(kind);
-            match t {
-                None => {
-                    res.command.insert(String::from(kind), senders);
-                }
-                Some(t) => t.append(&mut senders),
-            }
-        }
         res
     }
 }
@@ -89,9 +115,47 @@ impl<T: Subscriber> From<&T> for Module {
 pub struct Dispatcher {
     db: modules::Modules,
     api: Api,
-    modules: HashMap<String, Module>,
+    users: Arc<Mutex<Users>>,
+    permissions: Arc<Permissions>,
+    config: Arc<ConfigStore>,
+    factories: Vec<(String, Factory)>,
+    /// Moves the stored state of every registered module, enabled in the chat or not
+    migrations: Vec<(String, Migration)>,
+    modules: HashMap<String, Supervised>,
+    username: String,
     loaded_modules: Vec<String>,
-    chats: HashMap<telegram_bot::ChatId, HashMap<String, Module>>,
+    chats: HashMap<telegram_bot::ChatId, HashSet<String>>,
+    recorder: Option<record::Recorder>,
+    update_mode: UpdateMode,
+}
+
+/// Long polling timeout of `getUpdates`, seconds
+const POLL_TIMEOUT: u64 = 10;
+/// How long a replay keeps running after the last update
+const REPLAY_TAIL: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+/// A replay moves the clock by at most this much before the bot has to settle
+const REPLAY_STEP: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+
+/// Moves the paused clock in steps, so timers firing meanwhile are handled at their time
+async fn advance(fake: &FakeApi, by: tokio::time::Duration) {
+    let mut left = by;
+    while left > tokio::time::Duration::from_secs(0) {
+        let step = std::cmp::min(left, REPLAY_STEP);
+        tokio::time::advance(step).await;
+        fake.settle().await;
+        left -= step;
+    }
+}
+
+type Migration = Box<dyn Fn(telegram_bot::ChatId, telegram_bot::ChatId) -> Result<(), String> + Send>;
+
+/// Step of a chat migration which failed, the steps before it have already moved the chat
+#[derive(Debug)]
+pub enum MigrateError {
+    Modules(modules::ModulesError),
+    Config(ConfigError),
+    Module(String, String),
+    Users(UsersError),
 }
 
 pub trait Subscriber {
@@ -101,164 +165,468 @@ pub trait Subscriber {
     fn by_update_kind(&self) -> HashMap<UpdateKind, Vec<mpsc::Sender<telegram_bot::UpdateKind>>> {
         HashMap::new()
     }
-    fn by_command(&self) -> HashMap<&str, Vec<mpsc::Sender<Message>>> {
-        HashMap::new()
+    fn commands(&self) -> Vec<Command> {
+        vec!()
+    }
+    /// What to do with updates arriving while the module's queue is full
+    fn policy(&self) -> Policy {
+        Policy::Block
+    }
+    /// Per-chat settings read through the module's `Config`
+    fn settings() -> Vec<Setting> where Self: Sized {
+        vec!()
     }
 }
 
 impl Dispatcher {
-    pub fn new(api: Api) -> Dispatcher {
-        let db_path = std::env::var("DISPATCHER_DB").expect("Dispatcher db path is not set");
-        let mut db = modules::Modules::new(db_path).unwrap();
+    pub(crate) fn new(api: Api, users: Arc<Mutex<Users>>, settings: &Settings) -> Dispatcher {
+        let mut db = modules::Modules::new(&settings.dispatcher_db).unwrap();
 
         Dispatcher {
             db,
+            permissions: Arc::new(Permissions::new(api.clone(), settings.owners.clone())),
+            config: Arc::new(ConfigStore::new(&settings.dispatcher_db).unwrap()),
             api,
+            users,
+            factories: vec!(),
+            migrations: vec!(),
             modules: HashMap::new(),
+            username: String::new(),
             loaded_modules: vec!(),
             chats: HashMap::new(),
+            recorder: settings.record_updates.as_ref().map(record::Recorder::open),
+            update_mode: settings.update_mode.clone(),
         }
     }
 
-    async fn service_cmds(
-        &mut self,
-        kind: &telegram_bot::MessageKind,
-        chat: &ChatId,
-        user: &UserId,
-    ) {
-        let get_member = telegram_bot::GetChatMember::new(chat, user);
-        let member = crate::utils::must_send(&self.api, get_member).await.unwrap();
-        if !matches!(
-            member.status,
-            ChatMemberStatus::Administrator | ChatMemberStatus::Creator
-        ) {
-            return;
-        }
-        if let telegram_bot::MessageKind::Text { data, .. } = kind {
-            let mut parts = data.split(&[' ', '\n', '@'][..]);
-            if let Some(command) = parts.next() {
-                match command {
-                    "/enable" => {
-                        if let Some(name) = parts.next() {
-                            if let Some(module) = self.modules.get(name) {
-                                if let Some(module_list) = self.chats.get_mut(&chat) {
-                                    module_list.insert(name.to_string(), module.clone());
-                                } else {
-                                    let mut list = HashMap::new();
-                                    list.insert(name.to_string(), module.clone());
-                                    self.chats.insert(chat.clone(), list);
-                                }
-                                self.db.add(i64::from(chat.clone()), name);
-                                crate::utils::must_send(
-                                    &self.api,
-                                    SendMessage::new(chat, "Module enabled"),
-                                )
-                                .await;
-                            } else {
-                                crate::utils::must_send(
-                                    &self.api,
-                                    SendMessage::new(chat, "Module not found"),
-                                )
-                                .await;
-                            }
-                        } else {
-                            crate::utils::must_send(
-                                &self.api,
-                                SendMessage::new(chat, "Specify module name"),
-                            )
-                            .await;
+    /// Commands handled by the dispatcher itself
+    fn service_commands() -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::new("help", "commands.help_description"),
+            CommandSpec::new("enable", "modules.enable_description").admin().arg("module", ArgKind::Word),
+            CommandSpec::new("disable", "modules.disable_description").admin().arg("module", ArgKind::Word),
+            CommandSpec::new("modules", "modules.description").admin(),
+            CommandSpec::new("lang", "lang.description").admin().arg("lang", ArgKind::Choice(&["ru", "en"])),
+            CommandSpec::new("mentions", "mentions.description").admin().arg("style", ArgKind::Choice(&["notify", "silent"])),
+            CommandSpec::new("grant", "roles.grant_description").admin().arg("user", ArgKind::User).arg("role", ArgKind::Word),
+            CommandSpec::new("revoke", "roles.revoke_description").admin().arg("user", ArgKind::User).arg("role", ArgKind::Word),
+            CommandSpec::new("roles", "roles.description").admin(),
+            CommandSpec::new("config", "config.description").admin()
+                .optional("module", ArgKind::Word).optional("key", ArgKind::Word).optional("value", ArgKind::Text),
+        ]
+    }
+
+    async fn service_cmds(&mut self, msg: &Message) {
+        let (data, entities) = match &msg.kind {
+            telegram_bot::MessageKind::Text { data, entities } => (data, entities),
+            _ => return
+        };
+        let call = match Call::parse(data, entities, &self.username) {
+            Some(call) => call,
+            None => return
+        };
+        let specs = Dispatcher::service_commands();
+        let spec = match call.find(specs.iter()) {
+            Some(spec) => spec,
+            None => return
+        };
+        let invocation = match commands::invoke(&self.api, &self.permissions, &call, spec, msg).await {
+            Some(invocation) => invocation,
+            None => return
+        };
+        let chat = &msg.chat.id();
+        let lang = i18n::lang(*chat);
+        match invocation.command {
+            "help" => {
+                let empty = HashSet::new();
+                let enabled = self.chats.get(chat).unwrap_or(&empty);
+                let module_specs = self.loaded_modules.iter()
+                    .filter(|name| enabled.contains(*name))
+                    .flat_map(|name| self.modules[name].commands().iter());
+                let mut text = Text::markdown();
+                text.text(&commands::help(lang, specs.iter().chain(module_specs)));
+                crate::utils::send_text(&self.api, *chat, &text, None).await;
+            }
+            "enable" => {
+                let name = invocation.word("module").unwrap();
+                if self.modules.contains_key(name) {
+                    let key = match self.set_enabled(*chat, name, true) {
+                        Ok(()) => "modules.enabled",
+                        Err(e) => {
+                            println!("Module {} of {} not enabled: {:?}", name, i64::from(*chat), e);
+                            "modules.failed"
                         }
+                    };
+                    crate::utils::must_send(&self.api, SendMessage::new(chat, tr(lang, key, &[]))).await;
+                } else {
+                    crate::utils::must_send(
+                        &self.api,
+                        SendMessage::new(chat, tr(lang, "modules.not_found", &[])),
+                    )
+                    .await;
+                }
+            }
+            "disable" => {
+                let name = invocation.word("module").unwrap();
+                let key = match self.set_enabled(*chat, name, false) {
+                    Ok(()) => "modules.disabled",
+                    Err(e) => {
+                        println!("Module {} of {} not disabled: {:?}", name, i64::from(*chat), e);
+                        "modules.failed"
                     }
-                    "/disable" => {
-                        if let Some(name) = parts.next() {
-                            if let Some(module_list) = self.chats.get_mut(chat) {
-                                module_list.remove(name);
-                            }
-                            self.db.rm(i64::from(chat.clone()), name);
-                            crate::utils::must_send(
-                                &self.api,
-                                SendMessage::new(chat, "Module disabled"),
-                            )
-                            .await;
-                        } else {
-                            crate::utils::must_send(
-                                &self.api,
-                                SendMessage::new(chat, "Specify module name"),
-                            )
-                            .await;
-                        }
+                };
+                crate::utils::must_send(&self.api, SendMessage::new(chat, tr(lang, key, &[]))).await;
+            }
+            "modules" => {
+                let (text, keyboard) = self.modules_panel(lang, *chat);
+                let mut msg = SendMessage::new(chat, text.as_str().to_string());
+                msg.parse_mode(text.parse_mode());
+                msg.reply_markup(keyboard);
+                crate::utils::must_send(&self.api, msg).await;
+            }
+            "lang" => {
+                let new = Lang::parse(invocation.word("lang").unwrap()).unwrap();
+                let text = match self.db.set_lang(i64::from(*chat), new.code()) {
+                    Ok(()) => {
+                        i18n::set_lang(*chat, new);
+                        tr(new, "lang.set", &[])
                     }
-                    "/modules" => {
-                        let enabled = self.chats.get(chat).unwrap_or(&EMPTY_MODULELIST);
-                        let mut msg = "`Available modules: \n".to_string();
-                        for name in &self.loaded_modules {
-                            let status = if enabled.contains_key(name) { "+" } else { "-" };
-                            msg += format!("{} {}\n", status, &name).as_str();
-                        }
-                        msg += "`";
-                        crate::utils::must_send(
-                            &self.api,
-                            SendMessage::new(chat, msg)
-                                .parse_mode(telegram_bot::ParseMode::MarkdownV2),
-                        )
-                        .await;
+                    Err(e) => {
+                        println!("Language of {} not saved: {:?}", i64::from(*chat), e);
+                        tr(lang, "lang.failed", &[])
+                    }
+                };
+                crate::utils::must_send(&self.api, SendMessage::new(chat, text)).await;
+            }
+            "mentions" => {
+                let style = Mentions::parse(invocation.word("style").unwrap()).unwrap();
+                let key = match self.db.set_mentions(i64::from(*chat), style.name()) {
+                    Ok(()) => {
+                        markdown::set_mentions(*chat, style);
+                        if style == Mentions::Notify { "mentions.notify" } else { "mentions.silent" }
+                    }
+                    Err(e) => {
+                        println!("Mentions of {} not saved: {:?}", i64::from(*chat), e);
+                        "mentions.failed"
                     }
-                    _ => {}
+                };
+                crate::utils::must_send(&self.api, SendMessage::new(chat, tr(lang, key, &[]))).await;
+            }
+            command @ "grant" | command @ "revoke" => {
+                let role = invocation.word("role").unwrap();
+                let known = self.known_roles();
+                if !known.contains(&role) {
+                    let text = tr(lang, "roles.unknown", &[("roles", (&known.join(", ")).into())]);
+                    crate::utils::must_send(&self.api, SendMessage::new(chat, text)).await;
+                    return
                 }
+                let user = match invocation.users("user")[0] {
+                    UserRef::Id(id) => Ok(Some(*id)),
+                    UserRef::Username(name) => self.users.lock().await.user_by_username(name)
+                        .map(|user| user.map(|u| UserId::new(u.uid))),
+                };
+                let user = match user {
+                    Ok(Some(user)) => user,
+                    Ok(None) => {
+                        crate::utils::must_send(&self.api, SendMessage::new(chat, tr(lang, "roles.unknown_user", &[]))).await;
+                        return
+                    }
+                    Err(e) => {
+                        println!("Role {} of {} not changed: {:?}", role, i64::from(*chat), e);
+                        crate::utils::must_send(&self.api, SendMessage::new(chat, tr(lang, "roles.failed", &[]))).await;
+                        return
+                    }
+                };
+                // Permissions change only once the database has stored the change
+                let res = if command == "grant" {
+                    self.db.grant(i64::from(*chat), i64::from(user), role)
+                        .map(|_| self.permissions.grant(*chat, user, role))
+                        .map(|_| "roles.granted")
+                } else {
+                    self.db.revoke(i64::from(*chat), i64::from(user), role)
+                        .map(|_| self.permissions.revoke(*chat, user, role))
+                        .map(|_| "roles.revoked")
+                };
+                let key = match res {
+                    Ok(key) => key,
+                    Err(e) => {
+                        println!("Role {} of {} not changed: {:?}", role, i64::from(*chat), e);
+                        "roles.failed"
+                    }
+                };
+                crate::utils::must_send(&self.api, SendMessage::new(chat, tr(lang, key, &[("role", role.into())]))).await;
+            }
+            "config" => {
+                config_menu::command(&self.api, &self.config, &invocation).await;
             }
+            "roles" => {
+                let roles = self.permissions.roles(*chat);
+                let mut text = Text::markdown();
+                if roles.is_empty() {
+                    text.text(&tr(lang, "roles.empty", &[]));
+                } else {
+                    text.text(&tr(lang, "roles.list", &[]));
+                    let mut users = self.users.lock().await;
+                    for (user, roles) in roles {
+                        // The id is still useful when the name can't be loaded
+                        let name = users.user(i64::from(user)).unwrap_or_else(|e| {
+                                println!("User {} not loaded: {:?}", i64::from(user), e);
+                                None
+                            })
+                            .map(|u| markdown::full_name(&u.first_name, &u.last_name))
+                            .unwrap_or_else(|| i64::from(user).to_string());
+                        text.text(&format!("\n{}: {}", name, roles.join(", ")));
+                    }
+                }
+                crate::utils::send_text(&self.api, *chat, &text, None).await;
+            }
+            _ => {}
         }
     }
 
-    pub async fn start(&mut self) -> Result<(), Box<dyn Error>> {
+    /// The chat's modules change only once the database has stored the change
+    fn set_enabled(&mut self, chat: telegram_bot::ChatId, name: &str, enabled: bool) -> Result<(), modules::ModulesError> {
+        let modules = self.chats.entry(chat).or_default();
+        if enabled && !modules.contains(name) {
+            self.db.add(i64::from(chat), name)?;
+            modules.insert(name.to_string());
+        } else if !enabled {
+            self.db.rm(i64::from(chat), name)?;
+            modules.remove(name);
+        }
+        Ok(())
+    }
+
+    /// Moves everything of a group that became a supergroup to the new chat id. Telegram reports it
+    /// in both chats, the second call finds nothing left to move. Every stored step is idempotent, so
+    /// a failed migration is completed by that second call; memory only follows once everything is stored.
+    async fn migrate(&mut self, from: telegram_bot::ChatId, to: telegram_bot::ChatId) -> Result<(), MigrateError> {
+        println!("Migrating chat {} to {}", i64::from(from), i64::from(to));
+        self.db.migrate(i64::from(from), i64::from(to)).map_err(MigrateError::Modules)?;
+        self.config.migrate(from, to).map_err(MigrateError::Config)?;
+        for (name, migrate) in &self.migrations {
+            migrate(from, to).map_err(|e| MigrateError::Module(name.clone(), e))?;
+        }
+        self.users.lock().await.migrate_chat(i64::from(from), i64::from(to)).map_err(MigrateError::Users)?;
+
+        if let Some(modules) = self.chats.remove(&from) {
+            self.chats.entry(to).or_default().extend(modules);
+        }
+        self.config.migrate_values(from, to);
+        self.permissions.migrate(from, to);
+        i18n::migrate_lang(from, to);
+        markdown::migrate_mentions(from, to);
+        Ok(())
+    }
+
+    /// Roles required by module commands, the only ones that can be granted
+    fn known_roles(&self) -> Vec<&'static str> {
+        let mut res = self.modules.values()
+            .flat_map(|m| m.commands().iter())
+            .filter_map(|spec| match spec.permission {
+                Permission::Role(role) => Some(role),
+                _ => None
+            })
+            .collect::<Vec<&'static str>>();
+        res.sort();
+        res.dedup();
+        res
+    }
+
+    /// Registers service and module commands in the command menu of Telegram clients
+    async fn register_commands(&self) {
+        let mut specs = Dispatcher::service_commands();
+        for name in &self.loaded_modules {
+            for spec in self.modules[name].commands() {
+                if specs.iter().all(|s| s.name != spec.name) {
+                    specs.push(spec.clone());
+                }
+            }
+        }
+        crate::utils::must_send(&self.api, SetMyCommands::new(i18n::DEFAULT_LANG, None, specs.iter())).await;
+        for lang in i18n::LANGS.iter() {
+            crate::utils::must_send(&self.api, SetMyCommands::new(*lang, Some(lang.code()), specs.iter())).await;
+        }
+    }
+
+    /// Starts the modules and restores the per-chat state
+    async fn prepare(&mut self) {
+        let me = loop {
+            match crate::utils::must_send(&self.api, GetMe).await {
+                Some(me) => break me,
+                None => tokio::time::delay_for(tokio::time::Duration::from_secs(1)).await,
+            }
+        };
+        self.username = me.username.unwrap_or_default();
+        let ctx = Arc::new(Context {
+            api: self.api.clone(),
+            username: self.username.clone(),
+            permissions: self.permissions.clone(),
+        });
+        for (name, factory) in self.factories.drain(..) {
+            let supervised = Supervised::new(&name, factory, ctx.clone());
+            self.modules.insert(name, supervised);
+        }
         self.loaded_modules = self
             .modules
             .iter()
             .map(|x| x.0.clone())
             .collect::<Vec<String>>();
         self.loaded_modules.sort();
+        self.register_commands().await;
         for (chat, name) in self.db.modules().unwrap() {
             let id = telegram_bot::ChatId::from(chat);
-            if let Some(module) = self.modules.get(&name) {
-                if let Some(module_list) = self.chats.get_mut(&id) {
-                    module_list.insert(name, module.clone());
-                } else {
-                    let mut list = HashMap::new();
-                    list.insert(name.to_string(), module.clone());
-                    self.chats.insert(id, list);
-                }
+            if self.modules.contains_key(&name) {
+                self.chats.entry(id).or_default().insert(name);
             } else {
                 println!("Module {} not found!", &name);
             }
         }
-        let mut stream = self.api.stream();
-        while let Some(update) = stream.next().await {
-            let update = update?;
-            match &update.kind {
-                telegram_bot::UpdateKind::Message(Message {
-                    from, chat, kind, ..
-                })
-                | telegram_bot::UpdateKind::EditedMessage(Message {
-                    from, chat, kind, ..
-                }) => {
-                    self.service_cmds(kind, &chat.id(), &from.id).await;
-                    let cid = chat.id();
-                    for modules in &mut self.chats.get_mut(&cid) {
-                        for (_, module) in modules.iter_mut() {
-                            module.handle(&update).await?;
+        for (chat, code) in self.db.langs().unwrap() {
+            if let Some(lang) = Lang::parse(&code) {
+                i18n::set_lang(telegram_bot::ChatId::from(chat), lang);
+            }
+        }
+        for (chat, uid, role) in self.db.roles().unwrap() {
+            self.permissions.grant(telegram_bot::ChatId::from(chat), UserId::new(uid), &role);
+        }
+        for (chat, style) in self.db.mentions().unwrap() {
+            if let Some(style) = Mentions::parse(&style) {
+                markdown::set_mentions(telegram_bot::ChatId::from(chat), style);
+            }
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<(), Box<dyn Error>> {
+        self.prepare().await;
+        match self.update_mode.clone() {
+            UpdateMode::Polling => {
+                crate::utils::must_send(&self.api, webhook::DeleteWebhook {}).await;
+                let mut offset = 0;
+                loop {
+                    match self.api.send(record::GetUpdates::new(offset, POLL_TIMEOUT)).await {
+                        Ok(updates) => for update in updates {
+                            offset = std::cmp::max(offset, update["update_id"].as_i64().unwrap_or_default() + 1);
+                            self.handle_raw(update).await;
+                        },
+                        Err(e) => {
+                            println!("Failed to get updates: {}", e);
+                            tokio::time::delay_for(tokio::time::Duration::from_secs(1)).await;
                         }
                     }
                 }
-                _ => {
-                    for (_, module) in self.modules.iter_mut() {
-                        module.handle(&update).await?;
-                    }
+            }
+            UpdateMode::Webhook(config) => {
+                let mut updates = webhook::serve(&config)?;
+                while crate::utils::must_send(&self.api, webhook::SetWebhook::new(&config)).await.is_none() {
+                    tokio::time::delay_for(tokio::time::Duration::from_secs(1)).await;
+                }
+                while let Some(update) = updates.recv().await {
+                    self.handle_raw(update).await;
                 }
             }
         }
         Ok(())
     }
 
-    pub fn add_sub<T: Subscriber>(&mut self, name: String, sub: &T) {
-        self.modules.insert(name, Module::from(sub));
+    /// Feeds a recording made with `RECORD_UPDATES` through the modules keeping the original spacing
+    /// of the updates, then gives the modules' timers `REPLAY_TAIL` to fire. The clock is paused and only
+    /// moves once the bot answered everything before, so a recording replays the same way every time.
+    pub async fn replay<P: AsRef<std::path::Path>>(&mut self, path: P, fake: &FakeApi) -> Result<(), Box<dyn Error>> {
+        let updates = record::read(path)?;
+        self.recorder = None;
+        self.prepare().await;
+        tokio::time::pause();
+        fake.settle().await;
+        let first = updates.first().map_or(0, |(time, _)| *time);
+        let mut elapsed = 0;
+        for (time, mut update) in updates {
+            let at = std::cmp::max(time - first, 0) as u64;
+            advance(fake, tokio::time::Duration::from_millis(at.saturating_sub(elapsed))).await;
+            elapsed = std::cmp::max(elapsed, at);
+            fake.replace_ids(&mut update);
+            self.handle_raw(update).await;
+            fake.settle().await;
+        }
+        advance(fake, REPLAY_TAIL).await;
+        Ok(())
+    }
+
+    /// Records the update as it came and handles it if it's supported
+    async fn handle_raw(&mut self, update: serde_json::Value) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(&update);
+        }
+        match serde_json::from_value::<Update>(update) {
+            Ok(update) => self.handle_update(update).await,
+            Err(e) => println!("Unsupported update: {}", e),
+        }
+    }
+
+    async fn handle_update(&mut self, update: Update) {
+        match &update.kind {
+            telegram_bot::UpdateKind::Message(msg) => {
+                self.service_cmds(msg).await;
+                let migration = match msg.kind {
+                    telegram_bot::MessageKind::MigrateToChatId { data } => Some((msg.chat.id(), data.into())),
+                    telegram_bot::MessageKind::MigrateFromChatId { data } => Some((data.into(), msg.chat.id())),
+                    _ => None
+                };
+                let names = match migration {
+                    Some((from, to)) => {
+                        if let Err(e) = self.migrate(from, to).await {
+                            println!("Chat {} not migrated to {}: {:?}", i64::from(from), i64::from(to), e);
+                        }
+                        // Modules disabled in the chat may still keep something of it
+                        self.loaded_modules.iter().cloned().collect::<HashSet<String>>()
+                    }
+                    None => self.chats.get(&msg.chat.id()).cloned().unwrap_or_default(),
+                };
+                self.deliver(names.iter(), &update).await;
+            }
+            // Service commands are taken from edited messages too, so a mistyped one can be corrected
+            telegram_bot::UpdateKind::EditedMessage(msg) => {
+                self.service_cmds(msg).await;
+                let names = self.chats.get(&msg.chat.id()).cloned().unwrap_or_default();
+                self.deliver(names.iter(), &update).await;
+            }
+            telegram_bot::UpdateKind::CallbackQuery(query)
+                if query.data.as_deref().map_or(false, |d| d.starts_with(config_menu::CALLBACK_PREFIX)) => {
+                config_menu::callback(&self.api, &self.config, &self.permissions, query).await;
+            }
+            telegram_bot::UpdateKind::CallbackQuery(query)
+                if query.data.as_deref().map_or(false, |d| d.starts_with(modules_menu::CALLBACK_PREFIX)) => {
+                self.modules_callback(query).await;
+            }
+            _ => {
+                let names = self.loaded_modules.clone();
+                self.deliver(names.iter(), &update).await;
+            }
+        }
+    }
+
+    async fn deliver<'a, I: Iterator<Item = &'a String>>(&mut self, names: I, update: &Update) {
+        for name in names {
+            if let Some(supervised) = self.modules.get_mut(name) {
+                supervised.deliver(update).await;
+            }
+        }
+    }
+
+    /// Registers a module, it is started by `start`. `factory` is called again with fresh `Tasks`
+    /// to restart the module after one of its tasks panics or stops receiving updates.
+    pub fn add_sub<T, F>(&mut self, name: String, factory: F)
+        where T: Subscriber, F: Fn(&Tasks, Config) -> T + Send + 'static {
+        self.config.register(&name, T::settings());
+        let config = Config::new(self.config.clone(), &name);
+        self.factories.push((name, Box::new(move |tasks| Module::from(&factory(tasks, config.clone())))));
+    }
+
+    /// Registers how the module moves what it stores about a group to its supergroup. It runs whether
+    /// the module is enabled in the chat or not, the migration message is delivered to the module
+    /// afterwards, so a running instance can move what it keeps in memory.
+    pub fn add_migration<F>(&mut self, name: &str, migrate: F)
+        where F: Fn(telegram_bot::ChatId, telegram_bot::ChatId) -> Result<(), String> + Send + 'static {
+        self.migrations.push((name.to_string(), Box::new(migrate)));
     }
 }
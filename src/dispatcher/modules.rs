@@ -24,6 +24,22 @@ impl Modules {
                         PRIMARY KEY(chat_id, name))",
             params![],
         )?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS langs
+                        (chat_id INTEGER PRIMARY KEY, lang TEXT)",
+            params![],
+        )?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS mentions
+                        (chat_id INTEGER PRIMARY KEY, style TEXT)",
+            params![],
+        )?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS roles
+                        (chat_id INTEGER, uid INTEGER, role TEXT,
+                        PRIMARY KEY(chat_id, uid, role))",
+            params![],
+        )?;
         Ok(Modules { conn: db })
     }
 
@@ -47,4 +63,66 @@ impl Modules {
         Ok(())
 
     }
+
+    pub fn langs(&mut self) -> Result<Vec<(i64, String)>, ModulesError> {
+        let mut query = self.conn.prepare("SELECT chat_id, lang FROM langs")?;
+        let res = query.query_map(NO_PARAMS, |x| {
+            Ok((x.get(0)?, x.get(1)?))
+        })?.map(|x| x.unwrap()).collect();
+
+        Ok(res)
+    }
+
+    pub fn set_lang(&mut self, chat: i64, lang: &str) -> Result<(), ModulesError> {
+        let mut query = self.conn.prepare("INSERT OR REPLACE INTO langs (chat_id, lang) VALUES(?, ?)")?;
+        query.execute(params![chat, lang])?;
+        Ok(())
+    }
+
+    pub fn mentions(&mut self) -> Result<Vec<(i64, String)>, ModulesError> {
+        let mut query = self.conn.prepare("SELECT chat_id, style FROM mentions")?;
+        let res = query.query_map(NO_PARAMS, |x| {
+            Ok((x.get(0)?, x.get(1)?))
+        })?.map(|x| x.unwrap()).collect();
+
+        Ok(res)
+    }
+
+    pub fn set_mentions(&mut self, chat: i64, style: &str) -> Result<(), ModulesError> {
+        let mut query = self.conn.prepare("INSERT OR REPLACE INTO mentions (chat_id, style) VALUES(?, ?)")?;
+        query.execute(params![chat, style])?;
+        Ok(())
+    }
+
+    pub fn roles(&mut self) -> Result<Vec<(i64, i64, String)>, ModulesError> {
+        let mut query = self.conn.prepare("SELECT chat_id, uid, role FROM roles")?;
+        let res = query.query_map(NO_PARAMS, |x| {
+            Ok((x.get(0)?, x.get(1)?, x.get(2)?))
+        })?.map(|x| x.unwrap()).collect();
+
+        Ok(res)
+    }
+
+    pub fn grant(&mut self, chat: i64, uid: i64, role: &str) -> Result<(), ModulesError> {
+        let mut query = self.conn.prepare("INSERT OR IGNORE INTO roles (chat_id, uid, role) VALUES(?, ?, ?)")?;
+        query.execute(params![chat, uid, role])?;
+        Ok(())
+    }
+
+    pub fn revoke(&mut self, chat: i64, uid: i64, role: &str) -> Result<(), ModulesError> {
+        let mut query = self.conn.prepare("DELETE FROM roles WHERE chat_id=? AND uid=? AND role=?")?;
+        query.execute(params![chat, uid, role])?;
+        Ok(())
+    }
+
+    /// Moves everything of a group to its supergroup, rows the supergroup already has are kept
+    pub fn migrate(&mut self, from: i64, to: i64) -> Result<(), ModulesError> {
+        let tx = self.conn.transaction()?;
+        for table in &["modules", "langs", "mentions", "roles"] {
+            tx.execute(&format!("UPDATE OR IGNORE {} SET chat_id=? WHERE chat_id=?", table), params![to, from])?;
+            tx.execute(&format!("DELETE FROM {} WHERE chat_id=?", table), params![from])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
 }
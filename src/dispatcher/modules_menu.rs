@@ -0,0 +1,80 @@
+use crate::dispatcher::{config_menu, Dispatcher};
+use crate::dispatcher::supervisor::Health;
+use crate::i18n::{self, Lang, tr};
+use crate::markdown::{MarkdownV2, Text};
+use crate::utils::must_send;
+use std::collections::HashSet;
+use telegram_bot::{CallbackQuery, CanAnswerCallbackQuery, ChatId, EditMessageText, InlineKeyboardButton,
+                   InlineKeyboardMarkup, MessageOrChannelPost};
+
+/// Prefix of the callback data of `/modules` buttons
+pub(crate) const CALLBACK_PREFIX: &str = "mod:";
+
+impl Dispatcher {
+    /// Modules with their state, a button per module toggles it and another opens its settings
+    pub(super) fn modules_panel(&self, lang: Lang, chat: ChatId) -> (Text<MarkdownV2>, InlineKeyboardMarkup) {
+        let empty = HashSet::new();
+        let enabled = self.chats.get(&chat).unwrap_or(&empty);
+        let mut list = tr(lang, "modules.list", &[]);
+        let mut keyboard = InlineKeyboardMarkup::new();
+        for name in &self.loaded_modules {
+            let on = enabled.contains(name);
+            let health = match self.modules[name].health() {
+                Health::Running { restarts: 0 } => tr(lang, "modules.running", &[]),
+                Health::Running { restarts } => tr(lang, "modules.restarted", &[("n", (restarts as i64).into())]),
+                Health::Down { retry_in, .. } => tr(lang, "modules.down", &[("seconds", (retry_in.as_secs() as i64).into())]),
+            };
+            let (queued, dropped) = self.modules[name].queue_stats();
+            let queue = tr(lang, "modules.queue", &[("queued", (queued as i64).into()), ("dropped", (dropped as i64).into())]);
+            list += format!("\n{} {} ({}, {})", if on { "+" } else { "-" }, &name, health, queue).as_str();
+
+            // The button says what it does rather than toggling, so pressing a stale one twice is harmless
+            let (mark, op) = if on { ("✅", "off") } else { ("❌", "on") };
+            let mut row = vec![InlineKeyboardButton::callback(format!("{} {}", mark, name), format!("{}{}:{}", CALLBACK_PREFIX, op, name))];
+            if !self.config.schema(name).is_empty() {
+                row.push(InlineKeyboardButton::callback(tr(lang, "modules.settings", &[]), format!("{}config:{}", CALLBACK_PREFIX, name)));
+            }
+            keyboard.add_row(row);
+        }
+        let mut text = Text::markdown();
+        text.pre(&list);
+        (text, keyboard)
+    }
+
+    /// Handles a `/modules` button, the panel is edited in place
+    pub(super) async fn modules_callback(&mut self, query: &CallbackQuery) {
+        let msg = match &query.message {
+            Some(MessageOrChannelPost::Message(msg)) => msg,
+            _ => {
+                must_send(&self.api, query.acknowledge()).await;
+                return
+            }
+        };
+        if !self.permissions.is_admin(&msg.chat, query.from.id).await {
+            must_send(&self.api, query.acknowledge()).await;
+            return
+        }
+        let chat = msg.chat.id();
+        let lang = i18n::lang(chat);
+        let data = query.data.as_deref().unwrap_or_default().trim_start_matches(CALLBACK_PREFIX);
+        let (text, keyboard) = match data.split_once(':') {
+            Some(("config", name)) if self.modules.contains_key(name) => config_menu::menu(&self.config, lang, chat, name),
+            Some((op @ "on", name)) | Some((op @ "off", name)) => {
+                if self.modules.contains_key(name) {
+                    if let Err(e) = self.set_enabled(chat, name, op == "on") {
+                        println!("Module {} of {} not toggled: {:?}", name, i64::from(chat), e);
+                        must_send(&self.api, query.answer(tr(lang, "modules.failed", &[]))).await;
+                        return
+                    }
+                }
+                self.modules_panel(lang, chat)
+            }
+            _ => self.modules_panel(lang, chat),
+        };
+        must_send(&self.api, query.acknowledge()).await;
+        let mut edit = EditMessageText::new(chat, msg.id, text.as_str().to_string());
+        edit.parse_mode(text.parse_mode());
+        edit.reply_markup(keyboard);
+        must_send(&self.api, edit).await;
+    }
+}
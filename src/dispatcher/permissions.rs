@@ -0,0 +1,118 @@
+use crate::dispatcher::commands::Permission;
+use crate::utils::must_send;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use telegram_bot::{Api, ChatId, ChatMemberStatus, GetChatAdministrators, MessageChat, UserId};
+use tokio::time::{Duration, Instant};
+
+/// How long a fetched admin list is trusted
+const ADMINS_TTL: Duration = Duration::from_secs(300);
+
+/// Chat admins, bot owners and roles granted per chat
+pub(crate) struct Permissions {
+    api: Api,
+    owners: HashSet<UserId>,
+    admins: Mutex<HashMap<ChatId, (Instant, HashSet<UserId>)>>,
+    roles: RwLock<HashMap<ChatId, HashMap<UserId, HashSet<String>>>>,
+}
+
+impl Permissions {
+    pub fn new(api: Api, owners: HashSet<UserId>) -> Permissions {
+        Permissions {
+            api,
+            owners,
+            admins: Mutex::new(HashMap::new()),
+            roles: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_owner(&self, user: UserId) -> bool {
+        self.owners.contains(&user)
+    }
+
+    /// Owners are admins everywhere, everyone is an admin of their private chat with the bot
+    pub async fn is_admin(&self, chat: &MessageChat, user: UserId) -> bool {
+        if self.is_owner(user) {
+            return true
+        }
+        if let MessageChat::Private(_) = chat {
+            return true
+        }
+        self.admins(chat.id()).await.contains(&user)
+    }
+
+    async fn admins(&self, chat: ChatId) -> HashSet<UserId> {
+        let cached = self.admins.lock().unwrap().get(&chat).cloned();
+        if let Some((fetched, admins)) = &cached {
+            if fetched.elapsed() < ADMINS_TTL {
+                return admins.clone()
+            }
+        }
+        match must_send(&self.api, GetChatAdministrators::new(chat)).await {
+            Some(members) => {
+                let admins = members.into_iter()
+                    .filter(|m| matches!(m.status, ChatMemberStatus::Administrator | ChatMemberStatus::Creator))
+                    .map(|m| m.user.id)
+                    .collect::<HashSet<UserId>>();
+                self.admins.lock().unwrap().insert(chat, (Instant::now(), admins.clone()));
+                admins
+            }
+            // A stale list is better than locking admins out while the API is unavailable
+            None => cached.map(|(_, admins)| admins).unwrap_or_default()
+        }
+    }
+
+    pub fn has_role(&self, chat: ChatId, user: UserId, role: &str) -> bool {
+        self.roles.read().unwrap().get(&chat)
+            .and_then(|users| users.get(&user))
+            .map_or(false, |roles| roles.contains(role))
+    }
+
+    /// Whether the user may run a command requiring `permission`, `is_admin` as returned by `is_admin`
+    pub fn allows(&self, chat: ChatId, user: UserId, is_admin: bool, permission: Permission) -> bool {
+        match permission {
+            Permission::Anyone => true,
+            Permission::Admin => is_admin,
+            Permission::Role(role) => is_admin || self.has_role(chat, user, role),
+            Permission::Owner => self.is_owner(user),
+        }
+    }
+
+    pub fn grant(&self, chat: ChatId, user: UserId, role: &str) {
+        self.roles.write().unwrap().entry(chat).or_default().entry(user).or_default().insert(role.to_string());
+    }
+
+    pub fn revoke(&self, chat: ChatId, user: UserId, role: &str) {
+        if let Some(roles) = self.roles.write().unwrap().get_mut(&chat).and_then(|users| users.get_mut(&user)) {
+            roles.remove(role);
+        }
+    }
+
+    /// Moves the roles of a group to its supergroup, admins are fetched again for the new chat
+    pub fn migrate(&self, from: ChatId, to: ChatId) {
+        self.admins.lock().unwrap().remove(&from);
+        let mut roles = self.roles.write().unwrap();
+        if let Some(users) = roles.remove(&from) {
+            let target = roles.entry(to).or_default();
+            for (user, granted) in users {
+                target.entry(user).or_default().extend(granted);
+            }
+        }
+    }
+
+    /// Users with roles in the chat, sorted by user id
+    pub fn roles(&self, chat: ChatId) -> Vec<(UserId, Vec<String>)> {
+        let mut res = self.roles.read().unwrap().get(&chat)
+            .map(|users| users.iter()
+                .filter(|(_, roles)| !roles.is_empty())
+                .map(|(user, roles)| {
+                    let mut roles = roles.iter().cloned().collect::<Vec<String>>();
+                    roles.sort();
+                    (*user, roles)
+                })
+                .collect::<Vec<(UserId, Vec<String>)>>())
+            .unwrap_or_default();
+        res.sort_by_key(|(user, _)| i64::from(*user));
+        res
+    }
+}
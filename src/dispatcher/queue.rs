@@ -0,0 +1,231 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use telegram_bot::{ChatId, MessageOrChannelPost, Update, UpdateKind};
+use tokio::sync::Notify;
+
+/// What happens to an update when the module's queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Wait for free space, delaying delivery to every module
+    Block,
+    /// Drop the oldest queued update
+    DropOldest,
+    /// Drop the incoming update
+    DropNewest,
+    /// Separate queue per chat served in turn, a full chat queue drops the incoming update
+    PerChat,
+}
+
+pub const QUEUE_CAPACITY: usize = 1024;
+
+struct State {
+    /// Updates by chat, everything lives under `None` unless the policy is `PerChat`
+    queues: HashMap<Option<ChatId>, VecDeque<Update>>,
+    /// Chats with queued updates in the order they are served
+    order: VecDeque<Option<ChatId>>,
+    len: usize,
+}
+
+/// Buffer between the dispatcher and a module's forwarder task
+pub(crate) struct Queue {
+    policy: Policy,
+    capacity: usize,
+    state: std::sync::Mutex<State>,
+    items: Notify,
+    space: Notify,
+    dropped: AtomicU64,
+}
+
+fn chat(update: &Update) -> Option<ChatId> {
+    match &update.kind {
+        UpdateKind::Message(msg) | UpdateKind::EditedMessage(msg) => Some(msg.chat.id()),
+        UpdateKind::CallbackQuery(query) => match &query.message {
+            Some(MessageOrChannelPost::Message(msg)) => Some(msg.chat.id()),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+impl Queue {
+    pub fn new(policy: Policy, capacity: usize) -> Queue {
+        Queue {
+            policy,
+            capacity,
+            state: std::sync::Mutex::new(State { queues: HashMap::new(), order: VecDeque::new(), len: 0 }),
+            items: Notify::new(),
+            space: Notify::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().len
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Counts an update which was not queued at all, e.g. because the module is down
+    pub fn drop_update(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub async fn push(&self, update: Update) {
+        let key = if self.policy == Policy::PerChat { chat(&update) } else { None };
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                let state = &mut *state;
+                let queue = state.queues.entry(key).or_default();
+                // An evicted update leaves the chat in `order` even if its queue runs empty meanwhile
+                let queued = !queue.is_empty();
+                if queue.len() >= self.capacity {
+                    match self.policy {
+                        Policy::Block => {}
+                        Policy::DropOldest => {
+                            queue.pop_front();
+                            state.len -= 1;
+                            self.drop_update();
+                        }
+                        Policy::DropNewest | Policy::PerChat => {
+                            self.drop_update();
+                            return
+                        }
+                    }
+                }
+                if queue.len() < self.capacity {
+                    if !queued {
+                        state.order.push_back(key);
+                    }
+                    queue.push_back(update);
+                    state.len += 1;
+                    self.items.notify();
+                    return
+                }
+            }
+            self.space.notified().await;
+        }
+    }
+
+    pub async fn pop(&self) -> Update {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                let state = &mut *state;
+                if let Some(key) = state.order.pop_front() {
+                    let queue = state.queues.get_mut(&key).unwrap();
+                    let update = queue.pop_front().unwrap();
+                    if queue.is_empty() {
+                        state.queues.remove(&key);
+                    } else {
+                        state.order.push_back(key);
+                    }
+                    state.len -= 1;
+                    self.space.notify();
+                    return update
+                }
+            }
+            self.items.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::time::{delay_for, Duration};
+
+    fn update(id: i64, chat: i64) -> Update {
+        serde_json::from_value(serde_json::json!({
+            "update_id": id,
+            "message": {
+                "message_id": id,
+                "date": 0,
+                "chat": {"id": chat, "type": "group", "title": "test"},
+                "from": {"id": 1, "is_bot": false, "first_name": "test"},
+                "text": "test"
+            }
+        })).unwrap()
+    }
+
+    async fn pop_ids(queue: &Queue, n: usize) -> Vec<i64> {
+        let mut res = vec!();
+        for _ in 0..n {
+            res.push(queue.pop().await.id);
+        }
+        res
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let queue = Queue::new(Policy::DropOldest, 2);
+        for id in 1..=3 {
+            queue.push(update(id, -1)).await;
+        }
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(pop_ids(&queue, 2).await, vec!(2, 3));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_single_slot() {
+        let queue = Queue::new(Policy::DropOldest, 1);
+        for id in 1..=3 {
+            queue.push(update(id, -1)).await;
+        }
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(pop_ids(&queue, 1).await, vec!(3));
+        assert_eq!(queue.len(), 0);
+        queue.push(update(4, -1)).await;
+        assert_eq!(pop_ids(&queue, 1).await, vec!(4));
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let queue = Queue::new(Policy::DropNewest, 2);
+        for id in 1..=3 {
+            queue.push(update(id, -1)).await;
+        }
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(pop_ids(&queue, 2).await, vec!(1, 2));
+    }
+
+    #[tokio::test]
+    async fn per_chat_overflow_only_drops_the_full_chat() {
+        let queue = Queue::new(Policy::PerChat, 1);
+        queue.push(update(1, -1)).await;
+        queue.push(update(2, -1)).await;
+        queue.push(update(3, -2)).await;
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(pop_ids(&queue, 2).await, vec!(1, 3));
+    }
+
+    #[tokio::test]
+    async fn per_chat_round_robin() {
+        let queue = Queue::new(Policy::PerChat, 10);
+        for (id, chat) in &[(1, -1), (2, -1), (3, -1), (4, -2), (5, -3), (6, -2)] {
+            queue.push(update(*id, *chat)).await;
+        }
+        assert_eq!(queue.len(), 6);
+        assert_eq!(pop_ids(&queue, 6).await, vec!(1, 4, 5, 2, 6, 3));
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_space() {
+        let queue = Arc::new(Queue::new(Policy::Block, 1));
+        queue.push(update(1, -1)).await;
+        let pusher = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.push(update(2, -1)).await })
+        };
+        delay_for(Duration::from_millis(50)).await;
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop().await.id, 1);
+        pusher.await.unwrap();
+        assert_eq!(queue.pop().await.id, 2);
+        assert_eq!(queue.dropped(), 0);
+    }
+}
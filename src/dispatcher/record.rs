@@ -0,0 +1,68 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use telegram_bot::types::{HttpRequest, JsonIdResponse, JsonRequestType, RequestType, RequestUrl};
+use telegram_bot::Request;
+
+fn now_ms() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or_default()
+}
+
+/// Appends every incoming update to `RECORD_UPDATES` as a JSON line `{"time": <unix ms>, "update": {...}}`
+pub(crate) struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn open<P: AsRef<Path>>(path: P) -> Recorder {
+        let file = OpenOptions::new().create(true).append(true).open(path).expect("Can't open RECORD_UPDATES");
+        Recorder { file }
+    }
+
+    pub fn record(&mut self, update: &Value) {
+        if let Err(e) = writeln!(self.file, "{}", json!({"time": now_ms(), "update": update})) {
+            println!("Failed to record an update: {}", e);
+        }
+    }
+}
+
+/// Recorded updates with their times
+pub(crate) fn read<P: AsRef<Path>>(path: P) -> Result<Vec<(i64, Value)>, Box<dyn Error>> {
+    let mut res = vec!();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+        let mut entry = serde_json::from_str::<Value>(&line)?;
+        let time = entry["time"].as_i64().ok_or("update without time")?;
+        res.push((time, entry["update"].take()));
+    }
+    Ok(res)
+}
+
+/// Bot API `getUpdates` keeping updates as JSON, so they can be recorded as they came
+#[derive(Serialize, Debug)]
+pub(crate) struct GetUpdates {
+    offset: i64,
+    timeout: u64,
+}
+
+impl GetUpdates {
+    pub fn new(offset: i64, timeout: u64) -> GetUpdates {
+        GetUpdates { offset, timeout }
+    }
+}
+
+impl Request for GetUpdates {
+    type Type = JsonRequestType<Self>;
+    type Response = JsonIdResponse<Vec<Value>>;
+
+    fn serialize(&self) -> Result<HttpRequest, telegram_bot::types::Error> {
+        <Self::Type as RequestType>::serialize(RequestUrl::method("getUpdates"), self)
+    }
+}
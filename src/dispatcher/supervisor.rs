@@ -0,0 +1,300 @@
+use crate::dispatcher::{Context, Module};
+use crate::dispatcher::commands::CommandSpec;
+use crate::dispatcher::queue::{Queue, QUEUE_CAPACITY};
+use futures::future::{AbortHandle, Abortable};
+use futures::FutureExt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use telegram_bot::Update;
+use tokio::time::{Duration, Instant};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// A module running longer than this without failures restarts with the initial backoff again
+const STABLE_AFTER: Duration = Duration::from_secs(600);
+
+struct TasksInner {
+    name: String,
+    failed: AtomicBool,
+    handles: std::sync::Mutex<Vec<AbortHandle>>,
+}
+
+/// Tasks of one module instance. A panic in any of them marks the instance as failed,
+/// aborting stops the remaining ones before the module is restarted.
+#[derive(Clone)]
+pub struct Tasks {
+    inner: Arc<TasksInner>,
+}
+
+impl Tasks {
+    fn new(name: &str) -> Tasks {
+        Tasks {
+            inner: Arc::new(TasksInner {
+                name: name.to_string(),
+                failed: AtomicBool::new(false),
+                handles: std::sync::Mutex::new(vec!()),
+            })
+        }
+    }
+
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(&self, task: F) {
+        let (handle, registration) = AbortHandle::new_pair();
+        self.inner.handles.lock().unwrap().push(handle);
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            if AssertUnwindSafe(Abortable::new(task, registration)).catch_unwind().await.is_err() {
+                println!("Module {}: task panicked", inner.name);
+                inner.failed.store(true, Ordering::SeqCst);
+            }
+        });
+    }
+
+    fn fail(&self) {
+        self.inner.failed.store(true, Ordering::SeqCst);
+    }
+
+    fn failed(&self) -> bool {
+        self.inner.failed.load(Ordering::SeqCst)
+    }
+
+    fn abort(&self) {
+        for handle in self.inner.handles.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+pub(crate) enum Health {
+    Running { restarts: u32 },
+    Down { restarts: u32, retry_in: Duration },
+}
+
+pub(crate) type Factory = Box<dyn Fn(&Tasks) -> Module + Send>;
+
+/// Moves updates from the queue into the module's handlers, so a slow module only holds up its own queue
+async fn forward(queue: Arc<Queue>, mut module: Module, tasks: Tasks, ctx: Arc<Context>) {
+    loop {
+        let update = queue.pop().await;
+        if let Err(e) = module.handle(&update, &ctx).await {
+            println!("Module {}: delivery failed: {}", tasks.inner.name, e);
+            tasks.fail();
+            return
+        }
+    }
+}
+
+/// Module instance together with the factory used to restart it
+pub(crate) struct Supervised {
+    name: String,
+    factory: Factory,
+    tasks: Tasks,
+    running: bool,
+    /// Outlives module instances, so updates queued while the module restarts are not lost.
+    /// Created by the first instance which starts, its policy is needed for it.
+    queue: Option<Arc<Queue>>,
+    ctx: Arc<Context>,
+    commands: Vec<CommandSpec>,
+    started: Instant,
+    restarts: u32,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+impl Supervised {
+    pub fn new(name: &str, factory: Factory, ctx: Arc<Context>) -> Supervised {
+        let mut res = Supervised {
+            name: name.to_string(),
+            factory,
+            tasks: Tasks::new(name),
+            running: false,
+            queue: None,
+            ctx,
+            commands: vec!(),
+            started: Instant::now(),
+            restarts: 0,
+            backoff: INITIAL_BACKOFF,
+            retry_at: Instant::now(),
+        };
+        if !res.start() {
+            res.retry_later("start failed");
+        }
+        res
+    }
+
+    /// Runs a new instance of the module. The factory panics if it can't build one, e.g. on a locked
+    /// database, which leaves the module down.
+    fn start(&mut self) -> bool {
+        self.started = Instant::now();
+        self.tasks = Tasks::new(&self.name);
+        let (factory, tasks) = (&self.factory, &self.tasks);
+        let module = match std::panic::catch_unwind(AssertUnwindSafe(|| factory(tasks))) {
+            Ok(module) => module,
+            Err(_) => {
+                self.tasks.abort();
+                return false
+            }
+        };
+        let policy = module.policy;
+        let queue = self.queue.get_or_insert_with(|| Arc::new(Queue::new(policy, QUEUE_CAPACITY))).clone();
+        self.commands = module.commands.iter().map(|c| c.spec.clone()).collect();
+        self.tasks.spawn(forward(queue, module, self.tasks.clone(), self.ctx.clone()));
+        self.running = true;
+        true
+    }
+
+    /// Whether the module is running, it is restarted first if it crashed and its backoff has passed
+    fn check(&mut self) -> bool {
+        if self.running && self.tasks.failed() {
+            self.fail("task failed");
+        }
+        if !self.running && Instant::now() >= self.retry_at {
+            println!("Module {}: restarting", self.name);
+            self.restarts += 1;
+            if !self.start() {
+                self.retry_later("restart failed");
+            }
+        }
+        self.running
+    }
+
+    /// Queues the update according to the module's policy, only `Policy::Block` can wait here
+    pub async fn deliver(&mut self, update: &Update) {
+        let running = self.check();
+        match &self.queue {
+            Some(queue) if running => queue.push(update.clone()).await,
+            Some(queue) => queue.drop_update(),
+            None => {}
+        }
+    }
+
+    /// Stops the module, it is restarted after the backoff
+    fn fail(&mut self, reason: &str) {
+        if !self.running {
+            return
+        }
+        self.tasks.abort();
+        self.running = false;
+        self.retry_later(reason);
+    }
+
+    fn retry_later(&mut self, reason: &str) {
+        if self.started.elapsed() >= STABLE_AFTER {
+            self.backoff = INITIAL_BACKOFF;
+        }
+        println!("Module {} failed: {}, restarting in {:?}", self.name, reason, self.backoff);
+        self.retry_at = Instant::now() + self.backoff;
+        self.backoff = std::cmp::min(self.backoff * 2, MAX_BACKOFF);
+    }
+
+    pub fn health(&self) -> Health {
+        if self.running && !self.tasks.failed() {
+            Health::Running { restarts: self.restarts }
+        } else {
+            Health::Down {
+                restarts: self.restarts,
+                retry_in: self.retry_at.saturating_duration_since(Instant::now()),
+            }
+        }
+    }
+
+    pub fn commands(&self) -> &[CommandSpec] {
+        &self.commands
+    }
+
+    /// Updates waiting in the queue and updates dropped so far
+    pub fn queue_stats(&self) -> (usize, u64) {
+        self.queue.as_ref().map_or((0, 0), |queue| (queue.len(), queue.dropped()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatcher::Policy;
+    use crate::dispatcher::permissions::Permissions;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::AtomicU32;
+    use telegram_bot::Api;
+    use tokio::sync::oneshot;
+
+    fn context() -> Arc<Context> {
+        let api = Api::new("test-token");
+        Arc::new(Context { api: api.clone(), username: "bot".to_string(), permissions: Arc::new(Permissions::new(api, HashSet::new())) })
+    }
+
+    fn module() -> Module {
+        Module { message_kind: HashMap::new(), update_kind: HashMap::new(), commands: vec!(), policy: Policy::Block }
+    }
+
+    /// Factory panicking on the listed calls, counting from 0
+    fn factory(calls: Arc<AtomicU32>, panics: &'static [u32]) -> Factory {
+        Box::new(move |_: &Tasks| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            if panics.contains(&call) {
+                panic!("start {} failed", call);
+            }
+            module()
+        })
+    }
+
+    #[tokio::test]
+    async fn panicking_factory_backs_off() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let mut sup = Supervised::new("test", factory(calls.clone(), &[0, 1]), context());
+        assert!(matches!(sup.health(), Health::Down { restarts: 0, .. }));
+        assert!(sup.queue.is_none());
+        assert!(!sup.check());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        sup.retry_at = Instant::now();
+        assert!(!sup.check());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(sup.backoff, INITIAL_BACKOFF * 4);
+
+        sup.retry_at = Instant::now();
+        assert!(sup.check());
+        assert!(matches!(sup.health(), Health::Running { restarts: 2 }));
+        assert!(sup.queue.is_some());
+    }
+
+    #[tokio::test]
+    async fn failed_instance_is_aborted_and_restarted() {
+        let (tx, rx) = oneshot::channel::<()>();
+        let tx = std::sync::Mutex::new(Some(tx));
+        let mut sup = Supervised::new("test", Box::new(move |tasks: &Tasks| {
+            if let Some(tx) = tx.lock().unwrap().take() {
+                tasks.spawn(async move {
+                    let _tx = tx;
+                    futures::future::pending::<()>().await
+                });
+            }
+            module()
+        }), context());
+        assert!(sup.check());
+
+        sup.tasks.fail();
+        assert!(!sup.check());
+        // The sender is dropped together with the aborted task
+        assert!(rx.await.is_err());
+        assert!(matches!(sup.health(), Health::Down { restarts: 0, .. }));
+
+        sup.retry_at = Instant::now();
+        assert!(sup.check());
+        assert!(matches!(sup.health(), Health::Running { restarts: 1 }));
+    }
+
+    #[tokio::test]
+    async fn backoff_is_capped() {
+        let mut sup = Supervised::new("test", factory(Arc::new(AtomicU32::new(0)), &[]), context());
+        for _ in 0..20 {
+            sup.tasks.fail();
+            assert!(!sup.check());
+            sup.retry_at = Instant::now();
+            assert!(sup.check());
+        }
+        assert_eq!(sup.backoff, MAX_BACKOFF);
+        assert_eq!(sup.restarts, 20);
+    }
+}
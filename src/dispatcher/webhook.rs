@@ -0,0 +1,143 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Response, Server, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use telegram_bot::types::{HttpRequest, JsonRequestType, JsonTrueToUnitResponse, RequestType, RequestUrl};
+use telegram_bot::Request;
+use tokio::sync::mpsc;
+
+/// Header carrying the `secret_token` given to `setWebhook`
+const SECRET_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+/// How updates are received, `UPDATE_MODE=polling` (default) or `UPDATE_MODE=webhook`
+#[derive(Clone)]
+pub(crate) enum UpdateMode {
+    Polling,
+    Webhook(WebhookConfig),
+}
+
+#[derive(Clone)]
+pub(crate) struct WebhookConfig {
+    /// Public URL registered with Telegram, `WEBHOOK_URL`
+    pub url: String,
+    /// `WEBHOOK_ADDR`, the reverse proxy forwards to it
+    pub addr: SocketAddr,
+    /// `WEBHOOK_PATH`, requests to other paths are rejected
+    pub path: String,
+    /// `WEBHOOK_SECRET`, requests without it are rejected
+    pub secret: String,
+}
+
+impl UpdateMode {
+    pub fn from_env() -> UpdateMode {
+        match std::env::var("UPDATE_MODE").as_deref() {
+            Ok("webhook") => UpdateMode::Webhook(WebhookConfig {
+                url: std::env::var("WEBHOOK_URL").expect("WEBHOOK_URL not set"),
+                addr: std::env::var("WEBHOOK_ADDR").as_deref().unwrap_or("0.0.0.0:8080")
+                    .parse().expect("WEBHOOK_ADDR is not an address"),
+                path: std::env::var("WEBHOOK_PATH").unwrap_or_else(|_| "/".to_string()),
+                secret: std::env::var("WEBHOOK_SECRET").expect("WEBHOOK_SECRET not set"),
+            }),
+            Ok("polling") | Err(_) => UpdateMode::Polling,
+            Ok(mode) => panic!("Unknown UPDATE_MODE {}", mode),
+        }
+    }
+}
+
+struct Hook {
+    path: String,
+    secret: String,
+    updates: mpsc::Sender<Value>,
+}
+
+/// Looks at every byte, so the response time doesn't tell how much of the secret was guessed
+fn same_secret(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len() && given.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = code;
+    res
+}
+
+async fn receive(hook: Arc<Hook>, req: hyper::Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::POST || req.uri().path() != hook.path {
+        return Ok(status(StatusCode::NOT_FOUND))
+    }
+    let secret = req.headers().get(SECRET_HEADER).map(|v| v.as_bytes());
+    if !secret.map_or(false, |secret| same_secret(secret, hook.secret.as_bytes())) {
+        return Ok(status(StatusCode::UNAUTHORIZED))
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => {
+            println!("Webhook: failed to read the body: {}", e);
+            return Ok(status(StatusCode::BAD_REQUEST))
+        }
+    };
+    match serde_json::from_slice::<Value>(&body) {
+        // Waiting for the dispatcher here makes Telegram hold back further updates while it is busy
+        Ok(update) => if hook.updates.clone().send(update).await.is_err() {
+            return Ok(status(StatusCode::SERVICE_UNAVAILABLE))
+        },
+        // Telegram would redeliver it forever, so it's dropped
+        Err(e) => println!("Webhook: invalid update: {}", e),
+    }
+    Ok(status(StatusCode::OK))
+}
+
+/// Starts the HTTP server, updates it receives come out of the returned channel as JSON
+pub(crate) fn serve(config: &WebhookConfig) -> Result<mpsc::Receiver<Value>, hyper::Error> {
+    let (updates, res) = mpsc::channel(1024);
+    let hook = Arc::new(Hook { path: config.path.clone(), secret: config.secret.clone(), updates });
+    let server = Server::try_bind(&config.addr)?.serve(make_service_fn(move |_| {
+        let hook = hook.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| receive(hook.clone(), req))) }
+    }));
+    println!("Webhook: listening on {}", config.addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            println!("Webhook: server failed: {}", e);
+        }
+    });
+    Ok(res)
+}
+
+/// Bot API `setWebhook`
+#[derive(Serialize, Debug)]
+pub(crate) struct SetWebhook {
+    url: String,
+    secret_token: String,
+}
+
+impl SetWebhook {
+    pub fn new(config: &WebhookConfig) -> SetWebhook {
+        SetWebhook { url: config.url.clone(), secret_token: config.secret.clone() }
+    }
+}
+
+impl Request for SetWebhook {
+    type Type = JsonRequestType<Self>;
+    type Response = JsonTrueToUnitResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, telegram_bot::types::Error> {
+        <Self::Type as RequestType>::serialize(RequestUrl::method("setWebhook"), self)
+    }
+}
+
+/// Bot API `deleteWebhook`, long polling doesn't work while a webhook is set
+#[derive(Serialize, Debug)]
+pub(crate) struct DeleteWebhook {}
+
+impl Request for DeleteWebhook {
+    type Type = JsonRequestType<Self>;
+    type Response = JsonTrueToUnitResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, telegram_bot::types::Error> {
+        <Self::Type as RequestType>::serialize(RequestUrl::method("deleteWebhook"), self)
+    }
+}
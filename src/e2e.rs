@@ -0,0 +1,248 @@
+//! Scenarios running the whole bot against the fake Bot API
+
+use crate::dispatcher::UpdateMode;
+use crate::fake_api::FakeApi;
+use crate::settings::Settings;
+use crate::users::Users;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use telegram_bot::Api;
+use tokio::sync::Mutex;
+
+lazy_static! {
+    /// Every scenario talks to the same fake server, so they can't run in parallel
+    static ref SERIAL: Mutex<()> = Mutex::new(());
+}
+
+/// Fresh databases for the scenario
+fn environment(name: &str) -> Settings {
+    let dir = std::env::temp_dir().join(format!("quiz-bot-e2e-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    Settings {
+        dispatcher_db: dir.join("dispatcher.db"),
+        quester_db: dir.join("quester.db"),
+        users_db: dir.join("users.db"),
+        owners: HashSet::new(),
+        record_updates: None,
+        update_mode: UpdateMode::Polling,
+    }
+}
+
+/// Runs the bot until the scenario finishes
+async fn drive<F: Future<Output = ()>>(settings: &Settings, scenario: impl FnOnce(FakeApi, Arc<Mutex<Users>>) -> F) {
+    let fake = FakeApi::shared();
+    let users = Arc::new(Mutex::new(Users::new(&settings.users_db).unwrap()));
+    let mut disp = crate::build_dispatcher(Api::new("test-token"), users.clone(), settings);
+    tokio::select! {
+        res = disp.start() => panic!("dispatcher stopped: {:?}", res.err()),
+        _ = scenario(fake, users) => {}
+    }
+}
+
+/// Runs the bot with fresh databases until the scenario finishes, the scenario gets the quiz database
+async fn run<F: Future<Output = ()>>(name: &str, scenario: impl FnOnce(FakeApi, Arc<Mutex<Users>>, PathBuf) -> F) {
+    let _serial = SERIAL.lock().await;
+    let settings = environment(name);
+    let quester_db = settings.quester_db.clone();
+    drive(&settings, |fake, users| scenario(fake, users, quester_db)).await;
+}
+
+/// Group where `admin` enabled the module
+async fn group_with(fake: &mut FakeApi, id: i64, admin: &Value, module: &str) -> Value {
+    let chat = fake.group(id, "Test");
+    fake.set_admins(id, vec![admin.clone()]);
+    fake.message(&chat, admin, &format!("/enable {}", module));
+    fake.expect("sendMessage").await;
+    chat
+}
+
+/// Number the captcha asks to press
+fn captcha_task(text: &Value) -> u32 {
+    text.as_str().unwrap().chars().find_map(|c| c.to_digit(10)).unwrap()
+}
+
+#[tokio::test]
+async fn captcha_kicks_on_wrong_button() {
+    run("captcha-wrong", |mut fake, _, _| async move {
+        let admin = FakeApi::user(10, "Admin");
+        let newbie = FakeApi::user(20, "Newbie");
+        let chat = group_with(&mut fake, -1001, &admin, "captcha").await;
+
+        fake.join(&chat, &[newbie.clone()]);
+        let restrict = fake.expect("restrictChatMember").await;
+        assert_eq!(restrict.params["user_id"], 20);
+        let task = fake.expect("sendMessage").await;
+        let wrong = captcha_task(&task.params["text"]) % 9 + 1;
+
+        fake.callback(&newbie, &task.result, &wrong.to_string());
+        let kick = fake.expect("kickChatMember").await;
+        assert_eq!(kick.params["user_id"], 20);
+        let delete = fake.expect("deleteMessage").await;
+        assert_eq!(delete.params["message_id"], task.result["message_id"]);
+    }).await;
+}
+
+#[tokio::test]
+async fn captcha_lets_in_on_right_button() {
+    run("captcha-right", |mut fake, _, _| async move {
+        let admin = FakeApi::user(10, "Admin");
+        let newbie = FakeApi::user(20, "Newbie");
+        let chat = group_with(&mut fake, -1002, &admin, "captcha").await;
+
+        fake.join(&chat, &[newbie.clone()]);
+        fake.expect("restrictChatMember").await;
+        let task = fake.expect("sendMessage").await;
+
+        // Other users can't solve it for the newbie
+        fake.callback(&admin, &task.result, "1");
+        fake.callback(&newbie, &task.result, &captcha_task(&task.params["text"]).to_string());
+        let allow = fake.expect("restrictChatMember").await;
+        assert_eq!(allow.params["user_id"], 20);
+        assert_eq!(allow.params["permissions"]["can_send_messages"], true);
+        fake.expect("deleteMessage").await;
+        assert!(fake.calls().iter().all(|c| c.method != "kickChatMember"));
+    }).await;
+}
+
+#[tokio::test]
+async fn captcha_complains_without_rights() {
+    run("captcha-rights", |mut fake, _, _| async move {
+        let admin = FakeApi::user(10, "Admin");
+        let chat = group_with(&mut fake, -1003, &admin, "captcha").await;
+        fake.fail("restrictChatMember", "Bad Request: not enough rights to restrict/unrestrict chat member");
+
+        fake.join(&chat, &[FakeApi::user(20, "Newbie")]);
+        let complaint = fake.expect("sendMessage").await;
+        assert!(complaint.params["reply_markup"].is_null());
+    }).await;
+}
+
+fn add_question(quester_db: &Path) {
+    let mut quester = crate::quiz::quests::Quester::new(quester_db).unwrap();
+    for quest in crate::quiz::import::parse("Capital of France?\n+ Paris\n- Lyon\n").unwrap() {
+        quester.add_quest(&quest).unwrap();
+    }
+}
+
+/// Latest scores of the players in the chat
+async fn scores(users: &Mutex<Users>, chat: i64, players: &[i64]) -> Vec<i64> {
+    let mut users = users.lock().await;
+    players.iter()
+        .map(|id| users.score_history(chat, *id).unwrap().last().map_or(0, |(_, score)| *score))
+        .collect()
+}
+
+/// Plays a round with three players, two of them right
+async fn play_round(fake: &mut FakeApi, users: &Mutex<Users>, chat_id: i64) {
+    let admin = FakeApi::user(10, "Admin");
+    let chat = group_with(fake, chat_id, &admin, "quiz").await;
+
+    fake.message(&chat, &admin, "/quiz");
+    let poll = fake.expect("sendPoll").await;
+    assert_eq!(poll.params["question"], "Capital of France?");
+    let poll_id = poll.result["poll"]["id"].as_str().unwrap().to_string();
+    // The quiz takes answers once it got the sent poll back
+    fake.settle().await;
+
+    for (id, name, option) in &[(11, "Alice", 0), (12, "Bob", 0), (13, "Carol", 1)] {
+        fake.poll_answer(&poll_id, &FakeApi::user(*id, name), &[*option]);
+    }
+    fake.settle().await;
+    assert_eq!(scores(users, chat_id, &[11, 12, 13]).await, vec!(2, 2, -1));
+
+    fake.message(&chat, &admin, "/quiz stop");
+    let stop = fake.expect("stopPoll").await;
+    assert_eq!(stop.params["message_id"], poll.result["message_id"]);
+}
+
+#[tokio::test]
+async fn quiz_scores_answers() {
+    run("quiz-scores", |mut fake, users, quester_db| async move {
+        add_question(&quester_db);
+        play_round(&mut fake, &users, -1004).await;
+    }).await;
+}
+
+#[test]
+fn quiz_round_replays() {
+    let _serial = futures::executor::block_on(SERIAL.lock());
+    let runtime = || tokio::runtime::Builder::new().basic_scheduler().enable_all().build().unwrap();
+
+    let mut settings = environment("replay-recorded");
+    let recording = settings.users_db.with_file_name("updates.jsonl");
+    add_question(&settings.quester_db);
+    settings.record_updates = Some(recording.clone());
+    runtime().block_on(drive(&settings, |mut fake, users| async move {
+        play_round(&mut fake, &users, -1007).await;
+    }));
+
+    // Telegram issues other poll ids than the fake does
+    let recorded = std::fs::read_to_string(&recording).unwrap().lines()
+        .map(|line| {
+            let mut entry = serde_json::from_str::<Value>(line).unwrap();
+            if let Some(id) = entry["update"]["poll_answer"]["poll_id"].as_str().map(String::from) {
+                entry["update"]["poll_answer"]["poll_id"] = Value::from(format!("5{}", id));
+            }
+            entry.to_string()
+        })
+        .collect::<Vec<String>>();
+    std::fs::write(&recording, recorded.join("\n")).unwrap();
+
+    let settings = environment("replay-replayed");
+    add_question(&settings.quester_db);
+    runtime().block_on(async {
+        let mut fake = FakeApi::shared();
+        fake.set_admins(-1007, vec![FakeApi::user(10, "Admin")]);
+        let users = Arc::new(Mutex::new(Users::new(&settings.users_db).unwrap()));
+        crate::build_dispatcher(Api::new("test-token"), users.clone(), &settings).replay(&recording, &fake).await.unwrap();
+
+        assert_eq!(scores(&users, -1007, &[11, 12, 13]).await, vec!(2, 2, -1));
+        let methods = fake.take_calls().into_iter().map(|c| c.method).collect::<Vec<String>>();
+        assert_eq!(methods.iter().filter(|m| *m == "sendPoll").count(), 1);
+        assert_eq!(methods.iter().filter(|m| *m == "stopPoll").count(), 1);
+    });
+}
+
+/// Chains and active polls of the chat in the quiz database
+fn stored(quester_db: &Path, chat: i64) -> (i64, i64) {
+    let db = rusqlite::Connection::open(quester_db).unwrap();
+    let count = |table: &str| -> i64 {
+        db.query_row(&format!("SELECT COUNT(*) FROM {} WHERE chat_id = ?", table), rusqlite::params![chat], |row| row.get(0)).unwrap()
+    };
+    (count("chains"), count("active_polls"))
+}
+
+#[tokio::test]
+async fn migration_moves_disabled_quiz() {
+    run("migration", |mut fake, _, quester_db| async move {
+        add_question(&quester_db);
+        let admin = FakeApi::user(10, "Admin");
+        let group = group_with(&mut fake, -1006, &admin, "quiz").await;
+        fake.message(&group, &admin, "/quiz");
+        let poll = fake.expect("sendPoll").await;
+        fake.settle().await;
+        assert_eq!(stored(&quester_db, -1006), (1, 1));
+        // The quiz keeps running in the background, it is moved regardless
+        fake.message(&group, &admin, "/disable quiz");
+        fake.expect("sendMessage").await;
+
+        fake.migrate(&group, &admin, -1001006);
+        let supergroup = group_with(&mut fake, -1001006, &admin, "quiz").await;
+        // Updates are handled in order, so the stored quiz has moved by the time /enable is answered
+        assert_eq!(stored(&quester_db, -1006), (0, 0));
+        assert_eq!(stored(&quester_db, -1001006), (1, 1));
+
+        // The module moves its running quiz asynchronously
+        fake.settle().await;
+        fake.message(&supergroup, &admin, "/quiz stop");
+        let stop = fake.expect("stopPoll").await;
+        assert_eq!(stop.params["message_id"], poll.result["message_id"]);
+        let summary = fake.expect("sendMessage").await;
+        assert_eq!(summary.params["chat_id"], -1001006);
+    }).await;
+}
@@ -0,0 +1,502 @@
+//! In-process stand-in for the Bot API. Point `Api` at it with `TELEGRAM_API_URL`, push updates
+//! from a script and check the requests the bot made. Also used by `replay`.
+
+#[cfg(test)]
+use lazy_static::lazy_static;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+
+/// How long `expect` waits for the bot
+#[cfg(test)]
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Real time without requests after which `settle` considers the bot idle
+const SETTLE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Request made by the bot with the result it got
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub method: String,
+    pub params: Value,
+    pub result: Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Answers like Telegram would
+    Fake,
+    /// Prints every request and answers `true` to it, as if nothing was sent
+    DryRun,
+}
+
+#[derive(Default)]
+struct State {
+    updates: VecDeque<Value>,
+    last_update: i64,
+    last_message: i64,
+    last_poll: i64,
+    chats: HashMap<i64, Value>,
+    admins: HashMap<i64, Vec<Value>>,
+    /// Sent polls by message id
+    polls: HashMap<i64, Value>,
+    /// Ids of sent polls in the order they were sent
+    poll_ids: Vec<String>,
+    /// Latest versions of sent messages with buttons, in the order they were sent
+    keyboards: Vec<Value>,
+    /// Ids of recorded polls and messages by the ids issued for them here
+    replayed_polls: HashMap<String, String>,
+    replayed_messages: HashMap<(i64, i64), i64>,
+    /// Requests being answered
+    in_flight: usize,
+    /// Methods made to fail with the description
+    failing: HashMap<String, String>,
+    calls: Vec<Call>,
+}
+
+/// Counts a request as being answered until it is dropped, also when the bot hangs up meanwhile
+struct InFlight<'a>(&'a Shared);
+
+impl<'a> InFlight<'a> {
+    fn new(shared: &'a Shared) -> InFlight<'a> {
+        shared.state.lock().unwrap().in_flight += 1;
+        InFlight(shared)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+    }
+}
+
+struct Shared {
+    mode: Mode,
+    bot: Value,
+    state: Mutex<State>,
+    updated: Notify,
+    called: Notify,
+}
+
+pub struct FakeApi {
+    shared: Arc<Shared>,
+    url: String,
+    /// Calls before it were already matched by `expect`
+    seen: usize,
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+impl State {
+    fn chat(&self, id: &Value) -> Value {
+        let id = id.as_i64().unwrap_or_default();
+        self.chats.get(&id).cloned().unwrap_or_else(|| if id > 0 {
+            json!({"id": id, "type": "private", "first_name": "User"})
+        } else {
+            json!({"id": id, "type": "supergroup", "title": "Chat"})
+        })
+    }
+
+    /// Message sent by the bot, `text` and `reply_markup` are copied from the request
+    fn message(&mut self, bot: &Value, params: &Value, id: Option<i64>) -> Value {
+        let id = id.unwrap_or_else(|| {
+            self.last_message += 1;
+            self.last_message
+        });
+        let mut msg = json!({"message_id": id, "date": now(), "chat": self.chat(&params["chat_id"]), "from": bot});
+        for key in &["text", "reply_markup"] {
+            if !params[*key].is_null() {
+                msg[*key] = params[*key].clone();
+            }
+        }
+        msg
+    }
+
+    fn keep_keyboard(&mut self, msg: &Value) {
+        if msg["reply_markup"].is_null() {
+            return
+        }
+        let same = |m: &Value| m["chat"]["id"] == msg["chat"]["id"] && m["message_id"] == msg["message_id"];
+        match self.keyboards.iter_mut().find(|m| same(m)) {
+            Some(kept) => *kept = msg.clone(),
+            None => self.keyboards.push(msg.clone()),
+        }
+    }
+
+    /// The latest poll sent here not matched to a recorded one yet, unless the recorded one was matched already
+    fn replayed_poll(&mut self, recorded: &str) -> Option<String> {
+        if let Some(id) = self.replayed_polls.get(recorded) {
+            return Some(id.clone())
+        }
+        let id = self.poll_ids.iter().rev().find(|id| !self.replayed_polls.values().any(|m| m == *id))?.clone();
+        self.replayed_polls.insert(recorded.to_string(), id.clone());
+        Some(id)
+    }
+
+    /// Like `replayed_poll`, among the messages with buttons sent to the chat
+    fn replayed_message(&mut self, chat: i64, recorded: i64) -> Option<Value> {
+        let id = match self.replayed_messages.get(&(chat, recorded)) {
+            Some(id) => *id,
+            None => {
+                let mapped = &self.replayed_messages;
+                let id = self.keyboards.iter().rev()
+                    .filter(|m| m["chat"]["id"].as_i64() == Some(chat))
+                    .filter_map(|m| m["message_id"].as_i64())
+                    .find(|id| !mapped.iter().any(|((c, _), m)| *c == chat && m == id))?;
+                self.replayed_messages.insert((chat, recorded), id);
+                id
+            }
+        };
+        self.keyboards.iter().find(|m| m["chat"]["id"].as_i64() == Some(chat) && m["message_id"].as_i64() == Some(id)).cloned()
+    }
+
+    fn reply(&mut self, bot: &Value, method: &str, params: &Value) -> Result<Value, String> {
+        if let Some(description) = self.failing.get(method) {
+            return Err(description.clone())
+        }
+        let chat_id = params["chat_id"].as_i64().unwrap_or_default();
+        Ok(match method {
+            "getMe" => bot.clone(),
+            "sendMessage" | "sendPhoto" | "sendAudio" | "sendVideo" | "sendDocument" | "forwardMessage" => {
+                let msg = self.message(bot, params, None);
+                self.keep_keyboard(&msg);
+                msg
+            }
+            "editMessageText" | "editMessageReplyMarkup" => {
+                let id = params["message_id"].as_i64();
+                let msg = self.message(bot, params, id);
+                self.keep_keyboard(&msg);
+                msg
+            }
+            "sendPoll" => {
+                let mut msg = self.message(bot, params, None);
+                self.last_poll += 1;
+                let options = params["options"].as_array().cloned().unwrap_or_default().into_iter()
+                    .map(|text| json!({"text": text, "voter_count": 0}))
+                    .collect::<Vec<Value>>();
+                let mut poll = json!({
+                    "id": self.last_poll.to_string(),
+                    "question": params["question"],
+                    "options": options,
+                    "total_voter_count": 0,
+                    "is_closed": false,
+                    "is_anonymous": params["is_anonymous"].as_bool().unwrap_or(true),
+                    "type": params["type"].as_str().unwrap_or("regular"),
+                    "allows_multiple_answers": params["allows_multiple_answers"].as_bool().unwrap_or(false),
+                });
+                if !params["correct_option_id"].is_null() {
+                    poll["correct_option_id"] = params["correct_option_id"].clone();
+                }
+                self.polls.insert(msg["message_id"].as_i64().unwrap(), poll.clone());
+                self.poll_ids.push(self.last_poll.to_string());
+                msg["poll"] = poll;
+                msg
+            }
+            "stopPoll" => {
+                let poll = params["message_id"].as_i64().and_then(|id| self.polls.get_mut(&id))
+                    .ok_or_else(|| "Bad Request: message with poll to stop not found".to_string())?;
+                poll["is_closed"] = json!(true);
+                poll.clone()
+            }
+            "getUserProfilePhotos" => json!({"total_count": 0, "photos": []}),
+            "getChatAdministrators" => {
+                let admins = self.admins.get(&chat_id).cloned().unwrap_or_default();
+                Value::Array(admins.into_iter().map(|user| json!({"user": user, "status": "administrator"})).collect())
+            }
+            "getChatMember" => {
+                let user_id = params["user_id"].as_i64();
+                let admin = self.admins.get(&chat_id).and_then(|admins| admins.iter().find(|u| u["id"].as_i64() == user_id));
+                match admin {
+                    Some(user) => json!({"user": user, "status": "administrator"}),
+                    None => json!({"user": {"id": user_id, "is_bot": false, "first_name": "User"}, "status": "member"}),
+                }
+            }
+            // answerCallbackQuery, deleteMessage, restrictChatMember, kickChatMember, setMyCommands, ...
+            _ => json!(true),
+        })
+    }
+}
+
+/// Pending updates starting from `offset`, waits for new ones up to `timeout` seconds like long polling does
+async fn get_updates(shared: &Shared, params: &Value) -> Value {
+    let offset = params["offset"].as_i64().unwrap_or_default();
+    let deadline = Instant::now() + Duration::from_secs(params["timeout"].as_u64().unwrap_or_default());
+    loop {
+        {
+            let mut state = shared.state.lock().unwrap();
+            while state.updates.front().map_or(false, |u| u["update_id"].as_i64().unwrap_or_default() < offset) {
+                state.updates.pop_front();
+            }
+            if !state.updates.is_empty() || Instant::now() >= deadline {
+                return Value::Array(state.updates.iter().cloned().collect())
+            }
+        }
+        let _ = tokio::time::timeout_at(deadline, shared.updated.notified()).await;
+    }
+}
+
+async fn receive(shared: Arc<Shared>, req: hyper::Request<Body>) -> Result<Response<Body>, Infallible> {
+    // No files are stored, downloads must not go to the real server either
+    if req.uri().path().starts_with("/file/") {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = hyper::StatusCode::NOT_FOUND;
+        return Ok(res)
+    }
+    let method = req.uri().path().rsplit('/').next().unwrap_or_default().to_string();
+    let _in_flight = if method != "getUpdates" { Some(InFlight::new(&shared)) } else { None };
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+    // File uploads are multipart, they are recorded without parameters
+    let params = serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null);
+    let res = if method == "getUpdates" {
+        Ok(get_updates(&shared, &params).await)
+    } else {
+        let mut state = shared.state.lock().unwrap();
+        let res = match (shared.mode, method.as_str()) {
+            (Mode::DryRun, "getMe") => Ok(shared.bot.clone()),
+            (Mode::DryRun, _) => {
+                println!("-> {} {}", method, params);
+                Ok(json!(true))
+            }
+            (Mode::Fake, _) => state.reply(&shared.bot, &method, &params),
+        };
+        let result = res.clone().unwrap_or(Value::Null);
+        state.calls.push(Call { method, params, result });
+        shared.called.notify();
+        res
+    };
+    let body = match res {
+        Ok(result) => json!({"ok": true, "result": result}),
+        Err(description) => json!({"ok": false, "error_code": 400, "description": description}),
+    };
+    Ok(Response::new(Body::from(body.to_string())))
+}
+
+impl FakeApi {
+    /// Starts the server on a free local port, must be called inside the runtime
+    pub fn start(mode: Mode) -> FakeApi {
+        let (shared, url, server) = FakeApi::bind(mode);
+        tokio::spawn(server);
+        FakeApi { shared, url, seen: 0 }
+    }
+
+    fn bind(mode: Mode) -> (Arc<Shared>, String, impl std::future::Future<Output = hyper::Result<()>>) {
+        let shared = Arc::new(Shared {
+            mode,
+            bot: json!({"id": 1, "is_bot": true, "first_name": "Bot", "username": "test_bot"}),
+            state: Mutex::new(State::default()),
+            updated: Notify::new(),
+            called: Notify::new(),
+        });
+        let service = shared.clone();
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service_fn(move |_| {
+            let shared = service.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| receive(shared.clone(), req))) }
+        }));
+        let url = format!("http://{}/", server.local_addr());
+        (shared, url, server)
+    }
+
+    /// Value for `TELEGRAM_API_URL`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Replaces the ids the Bot API issued for polls and messages of the bot in a recorded update with
+    /// the ones issued here. A recorded id is matched to the latest unmatched poll or message with buttons
+    /// when it first shows up, which is right as long as answers come to the latest poll of the chat.
+    pub fn replace_ids(&self, update: &mut Value) {
+        let mut state = self.shared.state.lock().unwrap();
+        for (kind, key) in &[("poll_answer", "poll_id"), ("poll", "id")] {
+            if let Some(recorded) = update[*kind][*key].as_str().map(String::from) {
+                if let Some(id) = state.replayed_poll(&recorded) {
+                    update[*kind][*key] = json!(id);
+                }
+            }
+        }
+        if let Some(msg) = update.get_mut("callback_query").and_then(|query| query.get_mut("message")) {
+            if let (Some(chat), Some(recorded)) = (msg["chat"]["id"].as_i64(), msg["message_id"].as_i64()) {
+                if let Some(sent) = state.replayed_message(chat, recorded) {
+                    *msg = sent;
+                }
+            }
+        }
+    }
+
+    /// Waits until the bot fetched every update and stopped making requests. Replays run with the clock
+    /// paused, so this waits in real time.
+    pub async fn settle(&self) {
+        let mut last = None;
+        loop {
+            tokio::task::spawn_blocking(|| std::thread::sleep(SETTLE_INTERVAL)).await.unwrap();
+            let state = self.shared.state.lock().unwrap();
+            if state.in_flight == 0 && state.updates.is_empty() && last == Some(state.calls.len()) {
+                return
+            }
+            last = Some(state.calls.len());
+        }
+    }
+
+    /// Calls made since the previous `take_calls` or `expect`, `getUpdates` excluded
+    pub fn take_calls(&mut self) -> Vec<Call> {
+        let state = self.shared.state.lock().unwrap();
+        let res = state.calls[self.seen..].to_vec();
+        self.seen = state.calls.len();
+        res
+    }
+}
+
+#[cfg(test)]
+lazy_static! {
+    /// `Api` reads `TELEGRAM_API_URL` once per process, so every test talks to this server. It runs on
+    /// its own thread, the runtime of a single test would take it down when the test ends.
+    static ref SHARED: (Arc<Shared>, String) = {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build().unwrap();
+            runtime.block_on(async {
+                let (shared, url, server) = FakeApi::bind(Mode::Fake);
+                sender.send((shared, url)).unwrap();
+                server.await
+            })
+        });
+        let (shared, url) = receiver.recv().unwrap();
+        std::env::set_var("TELEGRAM_API_URL", &url);
+        (shared, url)
+    };
+}
+
+/// Scripting helpers for tests
+#[cfg(test)]
+impl FakeApi {
+    /// The server every test uses, forgetting what previous tests did. Update, message and poll ids
+    /// keep counting up, so the bot of a finished test can't take updates meant for the next one.
+    pub fn shared() -> FakeApi {
+        let (shared, url) = &*SHARED;
+        {
+            let mut state = shared.state.lock().unwrap();
+            let state = &mut *state;
+            *state = State {
+                last_update: state.last_update,
+                last_message: state.last_message,
+                last_poll: state.last_poll,
+                in_flight: state.in_flight,
+                ..State::default()
+            };
+        }
+        FakeApi { shared: shared.clone(), url: url.clone(), seen: 0 }
+    }
+
+    pub fn user(id: i64, first_name: &str) -> Value {
+        json!({"id": id, "is_bot": false, "first_name": first_name})
+    }
+
+    /// Supergroup used in updates and in messages sent to it
+    pub fn group(&self, id: i64, title: &str) -> Value {
+        let chat = json!({"id": id, "type": "supergroup", "title": title});
+        self.shared.state.lock().unwrap().chats.insert(id, chat.clone());
+        chat
+    }
+
+    pub fn set_admins(&self, chat: i64, users: Vec<Value>) {
+        self.shared.state.lock().unwrap().admins.insert(chat, users);
+    }
+
+    /// Makes every following call of the method fail
+    pub fn fail(&self, method: &str, description: &str) {
+        self.shared.state.lock().unwrap().failing.insert(method.to_string(), description.to_string());
+    }
+
+    /// Queues an update with a single field `kind`, returns its id
+    pub fn push(&self, kind: &str, value: Value) -> i64 {
+        let id = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.last_update += 1;
+            let id = state.last_update;
+            let mut update = json!({"update_id": id});
+            update[kind] = value;
+            state.updates.push_back(update);
+            id
+        };
+        self.shared.updated.notify();
+        id
+    }
+
+    fn next_message(&self) -> i64 {
+        let mut state = self.shared.state.lock().unwrap();
+        state.last_message += 1;
+        state.last_message
+    }
+
+    /// Text message from the user, a leading command gets its entity
+    pub fn message(&self, chat: &Value, from: &Value, text: &str) -> Value {
+        let mut msg = json!({"message_id": self.next_message(), "date": now(), "chat": chat, "from": from, "text": text});
+        if text.starts_with('/') {
+            let length = text.split_whitespace().next().unwrap_or_default().encode_utf16().count();
+            msg["entities"] = json!([{"type": "bot_command", "offset": 0, "length": length}]);
+        }
+        self.push("message", msg.clone());
+        msg
+    }
+
+    pub fn join(&self, chat: &Value, users: &[Value]) -> Value {
+        let from = users.first().cloned().unwrap_or(Value::Null);
+        let msg = json!({"message_id": self.next_message(), "date": now(), "chat": chat, "from": from, "new_chat_members": users});
+        self.push("message", msg.clone());
+        msg
+    }
+
+    /// Service message of a group which became the supergroup `to`
+    pub fn migrate(&self, chat: &Value, from: &Value, to: i64) -> Value {
+        let msg = json!({"message_id": self.next_message(), "date": now(), "chat": chat, "from": from, "migrate_to_chat_id": to});
+        self.push("message", msg.clone());
+        msg
+    }
+
+    /// Button press on a message sent by the bot, `message` as returned in `Call::result`
+    pub fn callback(&self, from: &Value, message: &Value, data: &str) -> i64 {
+        let id = self.shared.state.lock().unwrap().last_update + 1;
+        self.push("callback_query", json!({
+            "id": id.to_string(),
+            "from": from,
+            "message": message,
+            "chat_instance": "1",
+            "data": data,
+        }))
+    }
+
+    pub fn poll_answer(&self, poll_id: &str, user: &Value, options: &[i64]) -> i64 {
+        self.push("poll_answer", json!({"poll_id": poll_id, "user": user, "option_ids": options}))
+    }
+
+    /// Every call made so far, `getUpdates` excluded
+    pub fn calls(&self) -> Vec<Call> {
+        self.shared.state.lock().unwrap().calls.clone()
+    }
+
+    /// Waits for the next call of the method after the previously expected one, panics after a timeout
+    pub async fn expect(&mut self, method: &str) -> Call {
+        let deadline = Instant::now() + EXPECT_TIMEOUT;
+        loop {
+            {
+                let state = self.shared.state.lock().unwrap();
+                if let Some(pos) = state.calls[self.seen..].iter().position(|c| c.method == method) {
+                    self.seen += pos + 1;
+                    return state.calls[self.seen - 1].clone()
+                }
+            }
+            if tokio::time::timeout_at(deadline, self.shared.called.notified()).await.is_err() {
+                let calls = self.calls().into_iter().map(|c| c.method).collect::<Vec<String>>();
+                panic!("{} was not called, calls: {:?}", method, calls);
+            }
+        }
+    }
+}
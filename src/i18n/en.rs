@@ -0,0 +1,138 @@
+pub(super) const MESSAGES: &[(&str, &str)] = &[
+    ("quiz.no_answers", "Nobody answered :c\nWell, ask yourselves the questions then!"),
+    ("quiz.correct", "Big brains: "),
+    ("quiz.incorrect", "Not today: "),
+    ("quiz.hint", "Hint for {name} (-{cost}): {mask}"),
+    ("quiz.hint_failed", "Failed to buy the hint, try again later"),
+    ("quiz.tidy_on", "Old questions will be cleaned up"),
+    ("quiz.tidy_off", "Old questions will stay in the chat"),
+    ("quiz.stopped", "The quiz is stopped."),
+    ("quiz.rounds.one", "{n} round played"),
+    ("quiz.rounds.other", "{n} rounds played"),
+    ("quiz.no_chain", "No quiz is running"),
+    ("quiz.paused", "The quiz is paused, use /quiz resume to continue"),
+    ("quiz.no_questions", "There are no questions to ask"),
+    ("quiz.send_failed", "I can't send the questions, check that I may post media here"),
+    ("quiz.true", "True"),
+    ("quiz.false", "False"),
+    ("quiz.readtime", "Minimum reading time: {base} ms + {per_char} ms per character\n/quiz readtime <ms> <ms per character>"),
+    ("quiz.scoring_partial", "Partially correct answers get partial score"),
+    ("quiz.scoring_strict", "Only fully correct answers are scored"),
+    ("quiz.cheaters_none", "No cheaters found"),
+    ("quiz.cheaters", "Suspicious players:"),
+    ("quiz.cheaters_failed", "Failed to load the answers, try again later"),
+    ("quiz.cheater", "{name} (answers: {answers}, correct: {correct})"),
+    ("quiz.description", "start a quiz"),
+    ("quiz.playlist_description", "start a quiz from a playlist"),
+    ("quiz.stop_description", "stop the quiz"),
+    ("quiz.pause_description", "pause the quiz"),
+    ("quiz.resume_description", "resume the quiz"),
+    ("quiz.tidy_description", "remove old questions"),
+    ("quiz.readtime_description", "minimum time to read a question"),
+    ("quiz.scoring_description", "scoring of partially correct answers"),
+    ("quiz.cheaters_description", "suspicious players"),
+    ("quiz.hint_description", "hint for points"),
+    ("quiz.settings.tidy", "remove old questions"),
+    ("quiz.settings.scoring", "points for partially correct answers"),
+    ("quiz.settings.readtime_base", "minimum reading time, ms"),
+    ("quiz.settings.readtime_per_char", "reading time per character, ms"),
+    ("quiz.settings.round_seconds", "round duration, s"),
+    ("quiz.evidence_fast.one", "{n} answer faster than the minimum reading time, the fastest in {latency} ms"),
+    ("quiz.evidence_fast.other", "{n} answers faster than the minimum reading time, the fastest in {latency} ms"),
+    ("quiz.evidence_superhuman", "{accuracy}% correct answers in {latency} ms on average, chat average is {chat_latency} ms"),
+    ("playlist.finished", "The playlist is over!"),
+    ("playlist.not_found", "Playlist not found"),
+    ("playlist.usage", "/playlist new <name> [shuffle]\n/playlist add <name> <question id>\n/playlist add <name> category <category> [weight]\n/playlist show <name>\n/playlist delete <name>"),
+    ("playlist.created", "Playlist {name} created"),
+    ("playlist.exists", "Playlist {name} already exists, delete it first"),
+    ("playlist.added", "Added to playlist {name}"),
+    ("playlist.deleted", "Playlist {name} deleted"),
+    ("playlist.empty_list", "There are no playlists"),
+    ("playlist.list", "Playlists: {names}"),
+    ("playlist.ordered", "{name} (ordered)"),
+    ("playlist.shuffled", "{name} (shuffled)"),
+    ("playlist.category", "category {name} (weight {weight})"),
+    ("playlist.description", "list playlists"),
+    ("playlist.new_description", "create a playlist"),
+    ("playlist.add_description", "add a question or a category to a playlist"),
+    ("playlist.show_description", "show a playlist"),
+    ("playlist.delete_description", "delete a playlist"),
+    ("captcha.no_rights", "Whenever things get hard, I remind myself that giving up won't make it any better. Then I stop trying."),
+    ("captcha.task", "Hey. Press button {n} or I'll bite your face off"),
+    ("captcha.settings.timeout", "time to answer, s"),
+    ("antimoon.settings.filter", "emoji to delete"),
+    ("top.title", "Top, page {page} of {pages}:"),
+    ("top.prev", "◀️ Prev"),
+    ("top.me", "📍 My position"),
+    ("top.next", "Next ▶️"),
+    ("top.description", "top players"),
+    ("top.podium_description", "podium as a picture"),
+    ("rank.400", "Overmind"),
+    ("rank.300", "Walking Wikipedia"),
+    ("rank.240", "Supermind"),
+    ("rank.200", "Intellectual"),
+    ("rank.150", "Goes to 12th grade"),
+    ("rank.90", "Nerd"),
+    ("rank.40", "Something of a scientist myself"),
+    ("rank.10", "One and a half brain cells"),
+    ("rank.0", "Bread"),
+    ("rank.-10", "Miner"),
+    ("rank.-40", "Dota player"),
+    ("rank.-90", "♂DEEP ♂ DARK ♂ FANTASY♂"),
+    ("rank.-150", "♂DUNGEON♂ ♂MASTER♂"),
+    ("rank.-200", "♂BOSS OF THE GYM♂"),
+    ("rank.-240", "♂Oleg♂"),
+    ("rank.-300", "♂THREE HUNDRED BUCKS♂"),
+    ("rank.-400", "Our Game"),
+    ("rank.unknown", "MISSINGNO"),
+    ("modules.enabled", "Module enabled"),
+    ("modules.disabled", "Module disabled"),
+    ("modules.failed", "Failed to save the change, try again later"),
+    ("modules.not_found", "Module not found"),
+    ("modules.running", "running"),
+    ("modules.restarted.one", "restarted {n} time"),
+    ("modules.restarted.other", "restarted {n} times"),
+    ("modules.down", "down, restarting in {seconds} s"),
+    ("modules.queue", "{queued} queued, {dropped} dropped"),
+    ("modules.list", "Available modules:"),
+    ("modules.settings", "⚙️ Settings"),
+    ("modules.back", "◀️ Modules"),
+    ("modules.description", "list modules"),
+    ("modules.enable_description", "enable a module in the chat"),
+    ("modules.disable_description", "disable a module in the chat"),
+    ("lang.set", "Chat language: English"),
+    ("lang.failed", "Failed to save the language, try again later"),
+    ("lang.description", "chat language"),
+    ("mentions.notify", "Names will mention players"),
+    ("mentions.silent", "Names will link without notifying"),
+    ("mentions.failed", "Failed to save the setting, try again later"),
+    ("mentions.description", "whether to notify players"),
+    ("chart.empty", "There is no score history in this chat yet"),
+    ("chart.unknown_user", "I don't know @{name}"),
+    ("chart.too_many", "You can compare yourself with at most {n} players"),
+    ("chart.failed", "Failed to draw the chart"),
+    ("top.failed", "Failed to load the leaderboard, try again later"),
+    ("chart.description", "score chart"),
+    ("commands.usage", "Usage: {usage}"),
+    ("commands.help", "Commands:"),
+    ("commands.help_description", "list commands"),
+    ("commands.admin_only", " (admins only)"),
+    ("commands.role_only", " (role {role})"),
+    ("commands.owner_only", " (bot owners only)"),
+    ("roles.description", "roles in the chat"),
+    ("roles.grant_description", "grant a role"),
+    ("roles.revoke_description", "revoke a role"),
+    ("roles.granted", "Role {role} granted"),
+    ("roles.revoked", "Role {role} revoked"),
+    ("roles.unknown", "No such role, available: {roles}"),
+    ("roles.failed", "Failed to change the role, try again later"),
+    ("roles.unknown_user", "I don't know this user"),
+    ("roles.empty", "No roles in the chat"),
+    ("roles.list", "Roles:"),
+    ("config.description", "module settings"),
+    ("config.title", "Settings of {module}:"),
+    ("config.modules", "Modules with settings: {modules}\n/config <module> [key] [value]"),
+    ("config.unknown_module", "The module has no settings"),
+    ("config.unknown_key", "No such setting, available: {keys}"),
+    ("config.invalid", "Invalid value of {key}, expected {expected}"),
+];
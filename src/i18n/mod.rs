@@ -0,0 +1,213 @@
+mod en;
+mod ru;
+
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use telegram_bot::ChatId;
+use crate::markdown::{Format, Text};
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Lang {
+    Ru,
+    En,
+}
+
+pub const DEFAULT_LANG: Lang = Lang::Ru;
+pub const LANGS: [Lang; 2] = [Lang::Ru, Lang::En];
+
+impl Lang {
+    pub fn parse(code: &str) -> Option<Lang> {
+        match code {
+            "ru" => Some(Lang::Ru),
+            "en" => Some(Lang::En),
+            _ => None
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lang::Ru => "ru",
+            Lang::En => "en",
+        }
+    }
+
+    /// CLDR plural category of `n`
+    fn plural(&self, n: i64) -> &'static str {
+        let n = n.abs();
+        match self {
+            Lang::Ru => match (n % 10, n % 100) {
+                (1, m) if m != 11 => "one",
+                (2..=4, m) if !(12..=14).contains(&m) => "few",
+                _ => "many",
+            },
+            Lang::En => if n == 1 { "one" } else { "other" },
+        }
+    }
+
+    fn catalog(&self) -> &'static HashMap<&'static str, &'static str> {
+        match self {
+            Lang::Ru => &RU,
+            Lang::En => &EN,
+        }
+    }
+}
+
+lazy_static! {
+    static ref RU: HashMap<&'static str, &'static str> = ru::MESSAGES.iter().cloned().collect();
+    static ref EN: HashMap<&'static str, &'static str> = en::MESSAGES.iter().cloned().collect();
+    static ref CHAT_LANGS: RwLock<HashMap<ChatId, Lang>> = RwLock::new(HashMap::new());
+}
+
+/// Language selected in the chat
+pub fn lang(chat: ChatId) -> Lang {
+    CHAT_LANGS.read().unwrap().get(&chat).cloned().unwrap_or(DEFAULT_LANG)
+}
+
+pub fn set_lang(chat: ChatId, lang: Lang) {
+    CHAT_LANGS.write().unwrap().insert(chat, lang);
+}
+
+/// Moves the language of a group to its supergroup, unless the supergroup already has one
+pub fn migrate_lang(from: ChatId, to: ChatId) {
+    let mut langs = CHAT_LANGS.write().unwrap();
+    if let Some(lang) = langs.remove(&from) {
+        langs.entry(to).or_insert(lang);
+    }
+}
+
+/// Placeholder value
+pub enum Arg<'a> {
+    Str(&'a str),
+    Num(i64),
+}
+
+impl<'a> From<&'a str> for Arg<'a> {
+    fn from(s: &'a str) -> Self {
+        Arg::Str(s)
+    }
+}
+
+impl<'a> From<&'a String> for Arg<'a> {
+    fn from(s: &'a String) -> Self {
+        Arg::Str(s)
+    }
+}
+
+impl<'a> From<i64> for Arg<'a> {
+    fn from(n: i64) -> Self {
+        Arg::Num(n)
+    }
+}
+
+/// Placeholder value of a formatted message
+pub enum TextArg<'a, F: Format> {
+    Plain(Arg<'a>),
+    /// Already formatted text, inserted as is
+    Text(&'a Text<F>),
+}
+
+impl<'a, F: Format> From<&'a str> for TextArg<'a, F> {
+    fn from(s: &'a str) -> Self {
+        TextArg::Plain(Arg::Str(s))
+    }
+}
+
+impl<'a, F: Format> From<&'a String> for TextArg<'a, F> {
+    fn from(s: &'a String) -> Self {
+        TextArg::Plain(Arg::Str(s))
+    }
+}
+
+impl<'a, F: Format> From<i64> for TextArg<'a, F> {
+    fn from(n: i64) -> Self {
+        TextArg::Plain(Arg::Num(n))
+    }
+}
+
+impl<'a, F: Format> From<&'a Text<F>> for TextArg<'a, F> {
+    fn from(t: &'a Text<F>) -> Self {
+        TextArg::Text(t)
+    }
+}
+
+fn lookup(lang: Lang, key: &str) -> &'static str {
+    lang.catalog().get(key)
+        .or_else(|| DEFAULT_LANG.catalog().get(key))
+        .cloned()
+        .unwrap_or("")
+}
+
+/// Template of the message, `count` selects the plural form
+fn template(lang: Lang, key: &str, count: Option<i64>) -> &'static str {
+    if let Some(n) = count {
+        for l in &[lang, DEFAULT_LANG] {
+            let form = format!("{}.{}", key, l.plural(n));
+            if let Some(text) = l.catalog().get(form.as_str()) {
+                return *text
+            }
+        }
+    }
+    lookup(lang, key)
+}
+
+enum Piece<'t, A> {
+    Text(&'t str),
+    Arg(&'t A),
+}
+
+/// Splits the template at `{name}` placeholders
+fn pieces<'t, A>(template: &'t str, args: &'t [(&str, A)]) -> Vec<Piece<'t, A>> {
+    let mut res = vec!();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break
+        };
+        res.push(Piece::Text(&rest[..start]));
+        let name = &rest[start + 1..end];
+        match args.iter().find(|(n, _)| *n == name) {
+            Some((_, arg)) => res.push(Piece::Arg(arg)),
+            None => res.push(Piece::Text(&rest[start..=end])),
+        }
+        rest = &rest[end + 1..];
+    }
+    res.push(Piece::Text(rest));
+    res
+}
+
+/// Plain text message. A numeric `n` argument selects the plural form (`key.one`, `key.few`, ...)
+pub fn tr(lang: Lang, key: &str, args: &[(&str, Arg)]) -> String {
+    let count = args.iter().find_map(|(name, arg)| match (*name, arg) {
+        ("n", Arg::Num(n)) => Some(*n),
+        _ => None
+    });
+    let mut res = String::new();
+    for piece in pieces(template(lang, key, count), args) {
+        match piece {
+            Piece::Text(s) => res.push_str(s),
+            Piece::Arg(Arg::Str(s)) => res.push_str(s),
+            Piece::Arg(Arg::Num(n)) => res += &n.to_string(),
+        }
+    }
+    res
+}
+
+/// Formatted message, the template and plain arguments are escaped
+pub fn tr_text<F: Format>(lang: Lang, key: &str, args: &[(&str, TextArg<F>)]) -> Text<F> {
+    let count = args.iter().find_map(|(name, arg)| match (*name, arg) {
+        ("n", TextArg::Plain(Arg::Num(n))) => Some(*n),
+        _ => None
+    });
+    let mut res = Text::new();
+    for piece in pieces(template(lang, key, count), args) {
+        match piece {
+            Piece::Text(s) => res.text(s),
+            Piece::Arg(TextArg::Plain(Arg::Str(s))) => res.text(s),
+            Piece::Arg(TextArg::Plain(Arg::Num(n))) => res.text(&n.to_string()),
+            Piece::Arg(TextArg::Text(t)) => res.push(t),
+        };
+    }
+    res
+}
@@ -0,0 +1,141 @@
+pub(super) const MESSAGES: &[(&str, &str)] = &[
+    ("quiz.no_answers", "Никто не ответил:с\nНу и сами себе вопросы загадывайте!"),
+    ("quiz.correct", "Список Кодзим: "),
+    ("quiz.incorrect", "Список дэбилов: "),
+    ("quiz.hint", "Подсказка для {name} (-{cost}): {mask}"),
+    ("quiz.hint_failed", "Не удалось купить подсказку, попробуйте позже"),
+    ("quiz.tidy_on", "Буду убирать за собой старые вопросы"),
+    ("quiz.tidy_off", "Старые вопросы останутся в чате"),
+    ("quiz.stopped", "Викторина остановлена."),
+    ("quiz.rounds.one", "Сыгран {n} раунд"),
+    ("quiz.rounds.few", "Сыграно {n} раунда"),
+    ("quiz.rounds.many", "Сыграно {n} раундов"),
+    ("quiz.no_chain", "Викторина не запущена"),
+    ("quiz.paused", "Викторина на паузе, /quiz resume чтобы продолжить"),
+    ("quiz.no_questions", "Нет вопросов"),
+    ("quiz.send_failed", "Не получается отправить вопросы, проверьте, что мне можно публиковать медиа"),
+    ("quiz.true", "Правда"),
+    ("quiz.false", "Ложь"),
+    ("quiz.readtime", "Минимальное время на чтение: {base} мс + {per_char} мс на символ\n/quiz readtime <мс> <мс на символ>"),
+    ("quiz.scoring_partial", "Частично верные ответы приносят часть очков"),
+    ("quiz.scoring_strict", "Очки только за полностью верный ответ"),
+    ("quiz.cheaters_none", "Читеров не найдено"),
+    ("quiz.cheaters", "Подозрительные игроки:"),
+    ("quiz.cheaters_failed", "Не удалось загрузить ответы, попробуйте позже"),
+    ("quiz.cheater", "{name} (ответов: {answers}, верных: {correct})"),
+    ("quiz.description", "начать викторину"),
+    ("quiz.playlist_description", "начать викторину по плейлисту"),
+    ("quiz.stop_description", "остановить викторину"),
+    ("quiz.pause_description", "поставить викторину на паузу"),
+    ("quiz.resume_description", "продолжить викторину"),
+    ("quiz.tidy_description", "убирать старые вопросы"),
+    ("quiz.readtime_description", "минимальное время на чтение вопроса"),
+    ("quiz.scoring_description", "как считать очки за частично верные ответы"),
+    ("quiz.cheaters_description", "подозрительные игроки"),
+    ("quiz.hint_description", "подсказка за очки"),
+    ("quiz.settings.tidy", "убирать старые вопросы"),
+    ("quiz.settings.scoring", "очки за частично верные ответы"),
+    ("quiz.settings.readtime_base", "минимальное время на чтение, мс"),
+    ("quiz.settings.readtime_per_char", "время на чтение на символ, мс"),
+    ("quiz.settings.round_seconds", "длительность раунда, с"),
+    ("quiz.evidence_fast.one", "{n} ответ быстрее минимального времени чтения, самый быстрый за {latency} мс"),
+    ("quiz.evidence_fast.few", "{n} ответа быстрее минимального времени чтения, самый быстрый за {latency} мс"),
+    ("quiz.evidence_fast.many", "{n} ответов быстрее минимального времени чтения, самый быстрый за {latency} мс"),
+    ("quiz.evidence_superhuman", "{accuracy}% верных ответов в среднем за {latency} мс при {chat_latency} мс по чату"),
+    ("playlist.finished", "Плейлист закончился!"),
+    ("playlist.not_found", "Плейлист не найден"),
+    ("playlist.usage", "/playlist new <имя> [shuffle]\n/playlist add <имя> <номер вопроса>\n/playlist add <имя> category <категория> [вес]\n/playlist show <имя>\n/playlist delete <имя>"),
+    ("playlist.created", "Плейлист {name} создан"),
+    ("playlist.exists", "Плейлист {name} уже есть, сначала удалите его"),
+    ("playlist.added", "Добавлено в плейлист {name}"),
+    ("playlist.deleted", "Плейлист {name} удалён"),
+    ("playlist.empty_list", "Плейлистов нет"),
+    ("playlist.list", "Плейлисты: {names}"),
+    ("playlist.ordered", "{name} (по порядку)"),
+    ("playlist.shuffled", "{name} (вперемешку)"),
+    ("playlist.category", "категория {name} (вес {weight})"),
+    ("playlist.description", "список плейлистов"),
+    ("playlist.new_description", "создать плейлист"),
+    ("playlist.add_description", "добавить вопрос или категорию в плейлист"),
+    ("playlist.show_description", "показать плейлист"),
+    ("playlist.delete_description", "удалить плейлист"),
+    ("captcha.no_rights", "Когда мне тяжело, я всегда напоминаю себе о том, что если я сдамся – лучше не станет. А потом перестаю пытаться."),
+    ("captcha.task", "Слыш. Нажми кнопку {n}, а то ебло откушу"),
+    ("captcha.settings.timeout", "время на ответ, с"),
+    ("antimoon.settings.filter", "удаляемые эмодзи"),
+    ("top.title", "Топ, страница {page} из {pages}:"),
+    ("top.prev", "◀️ Назад"),
+    ("top.me", "📍 Моё место"),
+    ("top.next", "Вперёд ▶️"),
+    ("top.description", "топ игроков"),
+    ("top.podium_description", "пьедестал картинкой"),
+    ("rank.400", "Надмозг"),
+    ("rank.300", "Ходячая википедия"),
+    ("rank.240", "Сверхразум"),
+    ("rank.200", "Интеллектуал"),
+    ("rank.150", "Учится в 12 классе"),
+    ("rank.90", "Задрот"),
+    ("rank.40", "Знаете, и сам своего рода учёный"),
+    ("rank.10", "Полторы извилины"),
+    ("rank.0", "Хлеп"),
+    ("rank.-10", "Майн"),
+    ("rank.-40", " Дотер"),
+    ("rank.-90", "♂DEEP ♂ DARK ♂ FANTASY♂"),
+    ("rank.-150", "♂DUNGEON♂ ♂MASTER♂"),
+    ("rank.-200", "♂BOSS OF THE GYM♂"),
+    ("rank.-240", "♂Олег♂"),
+    ("rank.-300", "♂THREE HUNDRED BUCKS♂"),
+    ("rank.-400", "Наша Игра"),
+    ("rank.unknown", "MISSINGNO"),
+    ("modules.enabled", "Модуль включён"),
+    ("modules.disabled", "Модуль выключен"),
+    ("modules.failed", "Не удалось сохранить изменение, попробуйте позже"),
+    ("modules.not_found", "Модуль не найден"),
+    ("modules.running", "работает"),
+    ("modules.restarted.one", "перезапущен {n} раз"),
+    ("modules.restarted.few", "перезапущен {n} раза"),
+    ("modules.restarted.many", "перезапущен {n} раз"),
+    ("modules.down", "упал, перезапуск через {seconds} с"),
+    ("modules.queue", "в очереди {queued}, потеряно {dropped}"),
+    ("modules.list", "Доступные модули:"),
+    ("modules.settings", "⚙️ Настройки"),
+    ("modules.back", "◀️ Модули"),
+    ("modules.description", "список модулей"),
+    ("modules.enable_description", "включить модуль в чате"),
+    ("modules.disable_description", "выключить модуль в чате"),
+    ("lang.set", "Язык чата: русский"),
+    ("lang.failed", "Не удалось сохранить язык, попробуйте позже"),
+    ("lang.description", "язык чата"),
+    ("mentions.notify", "Имена будут упоминать игроков"),
+    ("mentions.silent", "Имена будут ссылками без уведомлений"),
+    ("mentions.failed", "Не удалось сохранить настройку, попробуйте позже"),
+    ("mentions.description", "упоминать игроков или нет"),
+    ("chart.empty", "Пока нет истории очков в этом чате"),
+    ("chart.unknown_user", "Не знаю пользователя @{name}"),
+    ("chart.too_many", "Сравнить себя можно не больше чем с {n} игроками"),
+    ("chart.failed", "Не получилось нарисовать график"),
+    ("top.failed", "Не удалось загрузить рейтинг, попробуйте позже"),
+    ("chart.description", "график очков"),
+    ("commands.usage", "Использование: {usage}"),
+    ("commands.help", "Команды:"),
+    ("commands.help_description", "список команд"),
+    ("commands.admin_only", " (для админов)"),
+    ("commands.role_only", " (роль {role})"),
+    ("commands.owner_only", " (для владельцев бота)"),
+    ("roles.description", "роли в чате"),
+    ("roles.grant_description", "выдать роль"),
+    ("roles.revoke_description", "забрать роль"),
+    ("roles.granted", "Роль {role} выдана"),
+    ("roles.revoked", "Роль {role} отобрана"),
+    ("roles.unknown", "Нет такой роли, есть: {roles}"),
+    ("roles.failed", "Не удалось изменить роль, попробуйте позже"),
+    ("roles.unknown_user", "Не знаю такого пользователя"),
+    ("roles.empty", "Ролей в чате нет"),
+    ("roles.list", "Роли:"),
+    ("config.description", "настройки модулей"),
+    ("config.title", "Настройки модуля {module}:"),
+    ("config.modules", "Модули с настройками: {modules}\n/config <модуль> [ключ] [значение]"),
+    ("config.unknown_module", "У модуля нет настроек"),
+    ("config.unknown_key", "Нет такой настройки, есть: {keys}"),
+    ("config.invalid", "Неверное значение {key}, ожидается {expected}"),
+];
@@ -8,35 +8,76 @@ mod utils;
 mod top;
 mod antimoon;
 mod captcha;
+mod i18n;
+mod render;
 pub(crate) mod markdown;
 pub(crate) mod users;
+mod settings;
+mod fake_api;
+#[cfg(test)]
+mod e2e;
 
 use std::env;
 use std::error::Error;
 use telegram_bot::Api;
 use tokio::sync::Mutex;
 use std::sync::Arc;
+use crate::settings::Settings;
+
+/// Dispatcher with every module registered
+fn build_dispatcher(api: Api, users: Arc<Mutex<users::Users>>, settings: &Settings) -> dispatcher::Dispatcher {
+    let mut disp = dispatcher::Dispatcher::new(api.clone(), users.clone(), settings);
+
+    let (quiz_api, quiz_users, quiz_db) = (api.clone(), users.clone(), settings.quester_db.clone());
+    disp.add_sub("quiz".to_string(), move |tasks, config| quiz::QuizModule::new(quiz_api.clone(), quiz_users.clone(), tasks, config, &quiz_db));
+    let quiz_db = settings.quester_db.clone();
+    disp.add_migration("quiz", move |from, to| quiz::QuizModule::migrate_chat(&quiz_db, from, to));
+
+    let (top_api, top_users) = (api.clone(), users.clone());
+    disp.add_sub("top".to_string(), move |tasks, _| top::UserTopModule::new(top_api.clone(), top_users.clone(), tasks));
+
+    let antimoon_api = api.clone();
+    disp.add_sub("antimoon".to_string(), move |tasks, config| antimoon::Antimoon::new(antimoon_api.clone(), tasks, config));
+
+    let captcha_api = api.clone();
+    disp.add_sub("captcha".to_string(), move |tasks, config| captcha::Captcha::new(captcha_api.clone(), tasks, config));
+
+    disp
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let args = env::args().collect::<Vec<String>>();
+    if args.len() == 3 && args[1] == "import" {
+        let mut quester = quiz::quests::Quester::new(env::var("QUESTER_DB").expect("QUESTER_DB not set")).unwrap();
+        let count = quiz::import::import(&mut quester, &args[2]).unwrap();
+        println!("Imported {} questions", count);
+        return Ok(())
+    }
+    // `replay <recording> [--dry-run]` feeds updates recorded with `RECORD_UPDATES` to the modules.
+    // Requests go to the fake API, point the *_DB variables at copies of the databases.
+    if args.len() >= 3 && args[1] == "replay" {
+        let mode = if args[3..].iter().any(|a| a == "--dry-run") { fake_api::Mode::DryRun } else { fake_api::Mode::Fake };
+        let mut fake = fake_api::FakeApi::start(mode);
+        env::set_var("TELEGRAM_API_URL", fake.url());
+        env::set_var("TELEGRAM_BOT_TOKEN", "replay");
+        let settings = Settings::from_env();
+        let users = Arc::new(Mutex::new(users::Users::new(&settings.users_db).unwrap()));
+        build_dispatcher(Api::new("replay"), users, &settings).replay(&args[2], &fake).await?;
+        if mode == fake_api::Mode::Fake {
+            for call in fake.take_calls() {
+                println!("-> {} {} <- {}", call.method, call.params, call.result);
+            }
+        }
+        return Ok(())
+    }
+
     let token = env::var("TELEGRAM_BOT_TOKEN");
-    let users = Arc::new(Mutex::new(users::Users::new(env::var("USERS_DB").expect("USERS_DB not set")).unwrap()));
+    let settings = Settings::from_env();
+    let users = Arc::new(Mutex::new(users::Users::new(&settings.users_db).unwrap()));
     let api = Api::new(token.unwrap());
 
-    let mut disp = dispatcher::Dispatcher::new(api.clone());
-
-    let quiz = quiz::QuizModule::new(api.clone(), users.clone());
-    disp.add_sub("quiz".to_string(), &quiz);
-
-    let top = top::UserTopModule::new(api.clone(), users.clone());
-    disp.add_sub("top".to_string(), &top);
-    
-    let antimoon = antimoon::Antimoon::new(api.clone());
-    disp.add_sub("antimoon".to_string(), &antimoon);
-    
-    let captcha = captcha::Captcha::new(api.clone());
-    disp.add_sub("captcha".to_string(), &captcha);
-
+    let mut disp = build_dispatcher(api, users, &settings);
     disp.start().await.unwrap();
     Ok(())
 }
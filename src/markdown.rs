@@ -1,15 +1,452 @@
+use std::fmt;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use telegram_bot::{ChatId, ParseMode, UserId};
+
+/// Entities which both formats can express
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entity<'a> {
+    Bold,
+    Italic,
+    Code,
+    Pre,
+    Link(&'a str),
+}
+
+/// Markup language of a `Text`. It is a type parameter, so texts in different formats can't be mixed.
+pub trait Format: Clone + Copy + fmt::Debug + PartialEq + Eq {
+    fn parse_mode() -> ParseMode;
+
+    fn escape_into(out: &mut String, data: &str);
+
+    /// Opening and closing markup of the entity and the escaping of its content
+    fn markup(entity: Entity) -> (String, String, fn(&mut String, &str));
+
+    /// Length in bytes of the character or escape sequence `data` starts with
+    fn unit_len(data: &str) -> usize;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarkdownV2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Html;
+
+impl Format for MarkdownV2 {
+    fn parse_mode() -> ParseMode {
+        ParseMode::MarkdownV2
+    }
+
+    fn escape_into(out: &mut String, data: &str) {
+        escape_markdown(out, data)
+    }
+
+    fn markup(entity: Entity) -> (String, String, fn(&mut String, &str)) {
+        match entity {
+            Entity::Bold => ("*".to_string(), "*".to_string(), escape_markdown),
+            Entity::Italic => ("_".to_string(), "_".to_string(), escape_markdown),
+            Entity::Code => ("`".to_string(), "`".to_string(), escape_markdown_code),
+            Entity::Pre => ("```\n".to_string(), "\n```".to_string(), escape_markdown_code),
+            Entity::Link(url) => {
+                let mut close = String::from("](");
+                escape_markdown_code(&mut close, url);
+                close.push(')');
+                ("[".to_string(), close, escape_markdown)
+            }
+        }
+    }
+
+    fn unit_len(data: &str) -> usize {
+        let mut chars = data.chars();
+        match chars.next() {
+            Some('\\') => 1 + chars.next().map_or(0, char::len_utf8),
+            Some(c) => c.len_utf8(),
+            None => 0,
+        }
+    }
+}
+
+impl Format for Html {
+    fn parse_mode() -> ParseMode {
+        ParseMode::Html
+    }
+
+    fn escape_into(out: &mut String, data: &str) {
+        escape_html(out, data)
+    }
+
+    fn markup(entity: Entity) -> (String, String, fn(&mut String, &str)) {
+        match entity {
+            Entity::Bold => ("<b>".to_string(), "</b>".to_string(), escape_html),
+            Entity::Italic => ("<i>".to_string(), "</i>".to_string(), escape_html),
+            Entity::Code => ("<code>".to_string(), "</code>".to_string(), escape_html),
+            Entity::Pre => ("<pre>".to_string(), "</pre>".to_string(), escape_html),
+            Entity::Link(url) => {
+                let mut open = String::from("<a href=\"");
+                escape_html(&mut open, url);
+                open.push_str("\">");
+                (open, "</a>".to_string(), escape_html)
+            }
+        }
+    }
+
+    fn unit_len(data: &str) -> usize {
+        match data.chars().next() {
+            Some('&') => data.find(';').map_or(data.len(), |i| i + 1),
+            Some(c) => c.len_utf8(),
+            None => 0,
+        }
+    }
+}
+
+fn escape_markdown(out: &mut String, data: &str) {
+    out.reserve(data.len());
+    for c in data.chars() {
+        if let '_' | '*' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '=' | '|' | '{' | '}' | '.' | '!' | '\\' = c {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Inside `pre`, `code` and link urls only `` ` ``, `)` and `\` are special
+fn escape_markdown_code(out: &mut String, data: &str) {
+    out.reserve(data.len());
+    for c in data.chars() {
+        if let '`' | ')' | '\\' = c {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+fn escape_html(out: &mut String, data: &str) {
+    out.reserve(data.len());
+    for c in data.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// How user names are rendered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mentions {
+    /// `tg://user?id=` mention, the user is notified
+    Notify,
+    /// Link to the public profile if the user has a username, plain name otherwise
+    Silent,
+}
+
+impl Mentions {
+    pub fn parse(name: &str) -> Option<Mentions> {
+        match name {
+            "notify" => Some(Mentions::Notify),
+            "silent" => Some(Mentions::Silent),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mentions::Notify => "notify",
+            Mentions::Silent => "silent",
+        }
+    }
+}
+
+lazy_static! {
+    static ref CHAT_MENTIONS: RwLock<HashMap<ChatId, Mentions>> = RwLock::new(HashMap::new());
+}
+
+/// Mention style selected in the chat
+pub fn mentions(chat: ChatId) -> Mentions {
+    CHAT_MENTIONS.read().unwrap().get(&chat).cloned().unwrap_or(Mentions::Notify)
+}
+
+pub fn set_mentions(chat: ChatId, style: Mentions) {
+    CHAT_MENTIONS.write().unwrap().insert(chat, style);
+}
+
+/// Moves the mention style of a group to its supergroup, unless the supergroup already has one
+pub fn migrate_mentions(from: ChatId, to: ChatId) {
+    let mut mentions = CHAT_MENTIONS.write().unwrap();
+    if let Some(style) = mentions.remove(&from) {
+        mentions.entry(to).or_insert(style);
+    }
+}
+
+/// Escapes plain text for `ParseMode::MarkdownV2`
 pub fn escape(data: &str) -> String {
-    let from = ["_", "*", "[", "]", "(", ")", "~", "`", ">", "#", "+", "-", "=", "|", "{", "}", ".", "!"];
-    let to = ["\\_", "\\*", "\\[", "\\]", "\\(", "\\)", "\\~", "\\`", "\\>", "\\#", "\\+", "\\-", "\\=", "\\|", "\\{", "\\}", "\\.", "\\!"];
-    let mut res = data.to_owned();
-    for c in 0..from.len() {
-        res = res.replace(from[c], to[c])
-    };
+    let mut res = String::new();
+    escape_markdown(&mut res, data);
     res
 }
 
-pub fn bold(data: &str) -> String {
-    format!("*{}*", data)
+/// Telegram limit for a single message
+pub const MESSAGE_LIMIT: usize = 4096;
+
+/// Byte ranges of a builder call: markup opening `start..inner_start`, content `inner_start..inner_end`
+/// and closing `inner_end..end`. Plain text has no markup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Segment {
+    start: usize,
+    inner_start: usize,
+    inner_end: usize,
+    end: usize,
+}
+
+impl Segment {
+    fn is_plain(&self) -> bool {
+        self.start == self.inner_start && self.inner_end == self.end
+    }
+}
+
+/// Message length as Telegram counts it
+fn text_len(data: &str) -> usize {
+    data.encode_utf16().count()
+}
+
+/// Formatted message text.
+///
+/// Plain strings are escaped on the way in, so markup can only be produced by the
+/// builder methods and a `Text` is always valid for its `Format`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Text<F: Format> {
+    buf: String,
+    segments: Vec<Segment>,
+    format: PhantomData<F>,
+}
+
+impl Text<MarkdownV2> {
+    pub fn markdown() -> Text<MarkdownV2> {
+        Text::new()
+    }
+}
+
+impl Text<Html> {
+    pub fn html() -> Text<Html> {
+        Text::new()
+    }
+}
+
+impl<F: Format> Text<F> {
+    pub fn new() -> Text<F> {
+        Text { buf: String::new(), segments: vec!(), format: PhantomData }
+    }
+
+    pub fn parse_mode(&self) -> ParseMode {
+        F::parse_mode()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.buf
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn text(&mut self, data: &str) -> &mut Self {
+        let start = self.buf.len();
+        F::escape_into(&mut self.buf, data);
+        let end = self.buf.len();
+        self.segments.push(Segment { start, inner_start: start, inner_end: end, end });
+        self
+    }
+
+    fn entity(&mut self, open: &str, close: &str, escape: fn(&mut String, &str), data: &str) -> &mut Self {
+        let start = self.buf.len();
+        self.buf.push_str(open);
+        let inner_start = self.buf.len();
+        escape(&mut self.buf, data);
+        let inner_end = self.buf.len();
+        self.buf.push_str(close);
+        let end = self.buf.len();
+        self.segments.push(Segment { start, inner_start, inner_end, end });
+        self
+    }
+
+    fn wrap(&mut self, entity: Entity, data: &str) -> &mut Self {
+        let (open, close, escape) = F::markup(entity);
+        self.entity(&open, &close, escape, data)
+    }
+
+    pub fn bold(&mut self, data: &str) -> &mut Self {
+        self.wrap(Entity::Bold, data)
+    }
+
+    pub fn italic(&mut self, data: &str) -> &mut Self {
+        self.wrap(Entity::Italic, data)
+    }
+
+    pub fn code(&mut self, data: &str) -> &mut Self {
+        self.wrap(Entity::Code, data)
+    }
+
+    pub fn pre(&mut self, data: &str) -> &mut Self {
+        self.wrap(Entity::Pre, data)
+    }
+
+    pub fn link(&mut self, label: &str, url: &str) -> &mut Self {
+        self.wrap(Entity::Link(url), label)
+    }
+
+    pub fn mention(&mut self, label: &str, user: UserId) -> &mut Self {
+        self.link(label, &format!("tg://user?id={}", user))
+    }
+
+    /// User name with `@username` where one exists, linked according to `style`
+    pub fn user(&mut self, id: UserId, name: &str, username: Option<&str>, style: Mentions) -> &mut Self {
+        let username = username.filter(|u| !u.is_empty());
+        match (style, username) {
+            (Mentions::Notify, None) => self.mention(name, id),
+            (Mentions::Notify, Some(username)) => self.mention(name, id).text(&format!(" (@{})", username)),
+            (Mentions::Silent, None) => self.bold(name),
+            (Mentions::Silent, Some(username)) => self.link(&format!("{} (@{})", name, username), &format!("https://t.me/{}", username)),
+        }
+    }
+
+    /// Appends another formatted text of the same format
+    pub fn push(&mut self, other: &Text<F>) -> &mut Self {
+        let offset = self.buf.len();
+        self.buf.push_str(&other.buf);
+        self.segments.extend(other.segments.iter().map(|s| Segment {
+            start: s.start + offset,
+            inner_start: s.inner_start + offset,
+            inner_end: s.inner_end + offset,
+            end: s.end + offset,
+        }));
+        self
+    }
+
+    /// Splits the text into messages of at most `limit` characters.
+    ///
+    /// Parts end at line breaks outside of entities where possible. A line which does not fit is
+    /// split between entities, and an entity which does not fit is closed and reopened in the next part.
+    pub fn split(&self, limit: usize) -> Vec<String> {
+        let mut parts = vec!();
+        let mut current = String::new();
+        for line in self.lines() {
+            let fits = text_len(&current) + text_len(&self.buf[line.clone()]) <= limit;
+            if fits {
+                current.push_str(&self.buf[line]);
+                continue
+            }
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+            }
+            if text_len(&self.buf[line.clone()]) <= limit {
+                current.push_str(&self.buf[line]);
+                continue
+            }
+            for piece in self.pieces(line, limit) {
+                if text_len(&current) + text_len(&piece) > limit {
+                    parts.push(std::mem::take(&mut current));
+                }
+                current.push_str(&piece);
+            }
+        }
+        if !current.is_empty() {
+            parts.push(current);
+        }
+        parts.into_iter()
+            .map(|buf| buf.trim_end_matches('\n').to_string())
+            .filter(|buf| !buf.is_empty())
+            .collect()
+    }
+
+    /// Byte ranges of lines, a line ends after a newline in plain text
+    fn lines(&self) -> Vec<std::ops::Range<usize>> {
+        let mut res = vec!();
+        let mut start = 0;
+        for s in self.segments.iter().filter(|s| s.is_plain()) {
+            for (i, _) in self.buf[s.start..s.end].match_indices('\n') {
+                let end = s.start + i + 1;
+                res.push(start..end);
+                start = end;
+            }
+        }
+        if start < self.buf.len() {
+            res.push(start..self.buf.len());
+        }
+        res
+    }
+
+    /// Formatted pieces of the line, each at most `limit` characters long
+    fn pieces(&self, line: std::ops::Range<usize>, limit: usize) -> Vec<String> {
+        let mut res = vec!();
+        for s in &self.segments {
+            if s.end <= line.start || s.start >= line.end {
+                continue
+            }
+            let (start, end) = if s.is_plain() {
+                (std::cmp::max(s.start, line.start), std::cmp::min(s.end, line.end))
+            } else {
+                (s.start, s.end)
+            };
+            if text_len(&self.buf[start..end]) <= limit {
+                res.push(self.buf[start..end].to_string());
+                continue
+            }
+            let open = &self.buf[s.start..s.inner_start];
+            let close = &self.buf[s.inner_end..s.end];
+            let (inner_start, inner_end) = if s.is_plain() { (start, end) } else { (s.inner_start, s.inner_end) };
+            let cap = limit.saturating_sub(text_len(open) + text_len(close)).max(1);
+            for chunk in self.chunks(&self.buf[inner_start..inner_end], cap) {
+                res.push(format!("{}{}{}", open, chunk, close));
+            }
+        }
+        res
+    }
+
+    /// Splits escaped text without separating an escape sequence from the escaped character,
+    /// preferring to break after a newline
+    fn chunks<'t>(&self, data: &'t str, cap: usize) -> Vec<&'t str> {
+        let mut res = vec!();
+        let mut start = 0;
+        let mut len = 0;
+        let mut newline = None;
+        let mut i = 0;
+        while i < data.len() {
+            let end = i + F::unit_len(&data[i..]);
+            let unit = text_len(&data[i..end]);
+            if len + unit > cap && len > 0 {
+                let split = newline.unwrap_or(i);
+                res.push(&data[start..split]);
+                len = text_len(&data[split..i]);
+                start = split;
+                newline = None;
+            }
+            len += unit;
+            if &data[i..end] == "\n" {
+                newline = Some(end);
+            }
+            i = end;
+        }
+        if start < data.len() {
+            res.push(&data[start..]);
+        }
+        res
+    }
+}
+
+impl<F: Format> Default for Text<F> {
+    fn default() -> Self {
+        Text::new()
+    }
+}
+
+impl<F: Format> fmt::Display for Text<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.buf)
+    }
 }
 
 pub fn full_name(first_name: &str, last_name: &str) -> String {
@@ -22,3 +459,100 @@ pub fn full_name(first_name: &str, last_name: &str) -> String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test::Bencher;
+
+    #[test]
+    fn escapes_every_special_character() {
+        assert_eq!(escape("_*[]()~`>#+-=|{}.!\\"), "\\_\\*\\[\\]\\(\\)\\~\\`\\>\\#\\+\\-\\=\\|\\{\\}\\.\\!\\\\");
+        assert_eq!(escape("Привет, мир"), "Привет, мир");
+    }
+
+    #[test]
+    fn markdown_entities() {
+        let mut text = Text::markdown();
+        text.text("score: -5 ").bold("a*b").text(" ").italic("x_y").text(" ").code("f(`x`)");
+        assert_eq!(text.as_str(), "score: \\-5 *a\\*b* _x\\_y_ `f(\\`x\\`\\)`");
+    }
+
+    #[test]
+    fn markdown_links() {
+        let mut text = Text::markdown();
+        text.link("[docs]", "https://example.com/a_(b)").text(" ").mention("Vasya P.", UserId::new(42));
+        assert_eq!(text.as_str(), "[\\[docs\\]](https://example.com/a_(b\\)) [Vasya P\\.](tg://user?id=42)");
+    }
+
+    #[test]
+    fn html_entities() {
+        let mut text = Text::html();
+        text.text("1 < 2 & ").bold("<b>").text(" ").code("a > b").text(" ").link("\"q\"", "https://example.com/?a=1&b=2");
+        assert_eq!(text.as_str(), "1 &lt; 2 &amp; <b>&lt;b&gt;</b> <code>a &gt; b</code> <a href=\"https://example.com/?a=1&amp;b=2\">&quot;q&quot;</a>");
+    }
+
+    #[test]
+    fn user_mentions() {
+        let mut text = Text::markdown();
+        text.user(UserId::new(1), "Vasya", Some("vasya_p"), Mentions::Notify).text(" ")
+            .user(UserId::new(2), "Petya", None, Mentions::Silent).text(" ")
+            .user(UserId::new(3), "Kolya", Some("kolya"), Mentions::Silent);
+        assert_eq!(text.as_str(), "[Vasya](tg://user?id=1) \\(@vasya\\_p\\) *Petya* [Kolya \\(@kolya\\)](https://t.me/kolya)");
+    }
+
+    #[test]
+    fn push_keeps_markup() {
+        let mut inner = Text::markdown();
+        inner.bold("x");
+        let mut outer = Text::markdown();
+        outer.text("(").push(&inner).text(")");
+        assert_eq!(outer.as_str(), "\\(*x*\\)");
+    }
+
+    #[test]
+    fn split_at_lines() {
+        let mut text = Text::markdown();
+        for i in 0..10 {
+            text.text(&format!("{}. ", i)).bold("name").text("\n");
+        }
+        let parts = text.split(40);
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(part.chars().count() <= 40);
+            assert_eq!(part.matches('*').count() % 2, 0);
+        }
+        let joined = parts.join("\n");
+        assert_eq!(joined, text.as_str().trim_end_matches('\n'));
+    }
+
+    #[test]
+    fn split_reopens_entities() {
+        let mut text = Text::markdown();
+        text.bold(&"a.".repeat(10));
+        let parts = text.split(10);
+        assert_eq!(parts, vec!["*a\\.a\\.a*", "*\\.a\\.a\\.*", "*a\\.a\\.a*", "*\\.a\\.a\\.*"]);
+    }
+
+    #[test]
+    fn split_keeps_html_entities() {
+        let mut text = Text::html();
+        text.text(&"&".repeat(5));
+        for part in text.split(10) {
+            assert!(part.split("&amp;").all(str::is_empty));
+        }
+    }
+
+    #[test]
+    fn short_text_is_not_split() {
+        let mut text = Text::markdown();
+        text.text("short").bold("text");
+        assert_eq!(text.split(MESSAGE_LIMIT), vec![text.as_str().to_string()]);
+    }
+
+    #[bench]
+    fn bench_escape(b: &mut Bencher) {
+        let data = "Какой (самый) большой город России? 1. Москва - 2. Санкт-Петербург! [см. wiki_page]".repeat(16);
+        b.iter(|| escape(&data));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::users::AnswerStats;
+use tokio::time::Duration;
+
+/// Answers below `min_reading_time` score zero
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ReadingTime {
+    pub base: u64,
+    pub per_char: u64,
+}
+
+impl ReadingTime {
+    pub fn min_reading_time(&self, text_len: usize) -> Duration {
+        Duration::from_millis(self.base + self.per_char * text_len as u64)
+    }
+}
+
+pub(super) enum Evidence {
+    /// Number of answers faster than the minimum reading time
+    TooFast(i64),
+    /// Almost every answer is correct and much faster than the chat average
+    Superhuman { accuracy: i64, latency: i64, chat_latency: i64 },
+}
+
+const MIN_ANSWERS: i64 = 10;
+const MIN_FLAGGED: i64 = 3;
+const SUPERHUMAN_ACCURACY: i64 = 95;
+const SUPERHUMAN_SPEEDUP: i64 = 3;
+
+fn median(mut data: Vec<i64>) -> i64 {
+    if data.is_empty() {
+        return 0
+    }
+    data.sort();
+    data[data.len() / 2]
+}
+
+pub(super) fn suspects(stats: Vec<AnswerStats>) -> Vec<(AnswerStats, Vec<Evidence>)> {
+    // Each player is compared to the others, their own answers would pull the baseline towards them
+    let baselines = (0..stats.len())
+        .map(|i| median(stats.iter().enumerate()
+            .filter(|(j, s)| *j != i && s.correct > 0)
+            .map(|(_, s)| s.avg_correct_latency)
+            .collect()))
+        .collect::<Vec<i64>>();
+
+    let mut res = vec!();
+    for (s, chat_latency) in stats.into_iter().zip(baselines) {
+        let mut evidence = vec!();
+        if s.flagged >= MIN_FLAGGED {
+            evidence.push(Evidence::TooFast(s.flagged));
+        }
+        let accuracy = s.correct * 100 / std::cmp::max(s.answers, 1);
+        if s.answers >= MIN_ANSWERS
+            && accuracy >= SUPERHUMAN_ACCURACY
+            && s.avg_correct_latency * SUPERHUMAN_SPEEDUP < chat_latency {
+            evidence.push(Evidence::Superhuman {
+                accuracy,
+                latency: s.avg_correct_latency,
+                chat_latency
+            });
+        }
+        if !evidence.is_empty() {
+            res.push((s, evidence));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::users::User;
+
+    fn stats(uid: i64, answers: i64, correct: i64, flagged: i64, latency: i64) -> AnswerStats {
+        AnswerStats {
+            user: User { uid, first_name: format!("User {}", uid), last_name: String::new(), username: String::new() },
+            answers,
+            correct,
+            flagged,
+            avg_correct_latency: latency,
+            min_latency: latency,
+        }
+    }
+
+    /// Uids of the suspects with the kinds of evidence against them
+    fn found(stats: Vec<AnswerStats>) -> Vec<(i64, Vec<&'static str>)> {
+        suspects(stats).into_iter()
+            .map(|(s, evidence)| (s.user.uid, evidence.iter().map(|e| match e {
+                Evidence::TooFast(_) => "too fast",
+                Evidence::Superhuman { .. } => "superhuman",
+            }).collect()))
+            .collect()
+    }
+
+    fn chat_with(suspect: AnswerStats) -> Vec<AnswerStats> {
+        vec![suspect, stats(2, 20, 10, 0, 9000), stats(3, 20, 10, 0, 10000), stats(4, 20, 10, 0, 11000)]
+    }
+
+    #[test]
+    fn empty_chat() {
+        assert!(found(vec!()).is_empty());
+    }
+
+    #[test]
+    fn too_fast_from_min_flagged() {
+        assert!(found(chat_with(stats(1, 20, 10, MIN_FLAGGED - 1, 10000))).is_empty());
+        assert_eq!(found(chat_with(stats(1, 20, 10, MIN_FLAGGED, 10000))), vec![(1, vec!["too fast"])]);
+    }
+
+    #[test]
+    fn superhuman_needs_min_answers() {
+        assert!(found(chat_with(stats(1, MIN_ANSWERS - 1, MIN_ANSWERS - 1, 0, 1000))).is_empty());
+        assert_eq!(found(chat_with(stats(1, MIN_ANSWERS, MIN_ANSWERS, 0, 1000))), vec![(1, vec!["superhuman"])]);
+    }
+
+    #[test]
+    fn superhuman_needs_accuracy() {
+        // 94% and 95% of 100 answers
+        assert!(found(chat_with(stats(1, 100, SUPERHUMAN_ACCURACY - 1, 0, 1000))).is_empty());
+        assert_eq!(found(chat_with(stats(1, 100, SUPERHUMAN_ACCURACY, 0, 1000))), vec![(1, vec!["superhuman"])]);
+    }
+
+    #[test]
+    fn superhuman_needs_speedup() {
+        // The others answer in 10s on median
+        assert!(found(chat_with(stats(1, 20, 20, 0, 10000 / SUPERHUMAN_SPEEDUP + 1))).is_empty());
+        assert_eq!(found(chat_with(stats(1, 20, 20, 0, 10000 / SUPERHUMAN_SPEEDUP - 1))), vec![(1, vec!["superhuman"])]);
+    }
+
+    #[test]
+    fn baseline_excludes_the_player() {
+        // Counting their own answers in, the median of the chat would be 1s and nobody would stand out
+        let chat = vec![
+            stats(1, 20, 20, 0, 1000), stats(2, 20, 20, 0, 1000), stats(3, 20, 20, 0, 1000),
+            stats(4, 20, 10, 0, 10000), stats(5, 20, 10, 0, 10000),
+        ];
+        let uids = found(chat).into_iter().map(|(uid, _)| uid).collect::<Vec<i64>>();
+        assert_eq!(uids, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn alone_is_not_superhuman() {
+        assert!(found(vec![stats(1, 20, 20, 0, 1000)]).is_empty());
+    }
+}
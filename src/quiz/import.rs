@@ -0,0 +1,164 @@
+use crate::quiz::quests::{Quester, Question, QuestionKind, Media, MediaKind, QuesterError};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ImportError {
+    IOError(std::io::Error),
+    DBError(QuesterError),
+    Syntax(usize, String),
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(e: std::io::Error) -> Self {
+        ImportError::IOError(e)
+    }
+}
+
+impl From<QuesterError> for ImportError {
+    fn from(e: QuesterError) -> Self {
+        ImportError::DBError(e)
+    }
+}
+
+/// Parses a question file. Questions are separated by empty lines:
+///
+/// ```text
+/// # comment
+/// Name this song
+/// media: audio ./songs/01.mp3
+/// + Never Gonna Give You Up
+/// - Together Forever
+/// ```
+///
+/// `media` is optional, its source is either a telegram `file_id` or a local path.
+/// `category: music` puts the question into a category used by playlists.
+/// `type: multiple` allows several correct answers, `type: truefalse` replaces
+/// the answer list with `answer: true` or `answer: false`, the options are labeled in the chat language.
+pub fn parse(data: &str) -> Result<Vec<Question>, ImportError> {
+    let mut res = vec!();
+    let mut current: Option<Question> = None;
+    for (n, line) in data.lines().enumerate() {
+        let line = line.trim();
+        let lineno = n + 1;
+        if line.starts_with('#') {
+            continue
+        }
+        if line.is_empty() {
+            if let Some(q) = current.take() {
+                res.push(check(q, lineno)?);
+            }
+            continue
+        }
+        match current.as_mut() {
+            None => {
+                current = Some(Question {
+                    id: 0,
+                    kind: QuestionKind::Single,
+                    category: None,
+                    text: line.to_string(),
+                    answers: vec!(),
+                    media: None
+                });
+            }
+            Some(q) => {
+                if let Some(answer) = line.strip_prefix('+') {
+                    q.answers.push((answer.trim().to_string(), true));
+                } else if let Some(answer) = line.strip_prefix('-') {
+                    q.answers.push((answer.trim().to_string(), false));
+                } else if let Some(kind) = line.strip_prefix("type:") {
+                    q.kind = QuestionKind::parse(kind.trim())
+                        .ok_or_else(|| ImportError::Syntax(lineno, "unknown question type".to_string()))?;
+                } else if let Some(category) = line.strip_prefix("category:") {
+                    q.category = Some(category.trim().to_string());
+                } else if let Some(answer) = line.strip_prefix("answer:") {
+                    let valid = match answer.trim() {
+                        "true" => true,
+                        "false" => false,
+                        _ => return Err(ImportError::Syntax(lineno, "answer must be true or false".to_string()))
+                    };
+                    q.answers = vec![("true".to_string(), valid), ("false".to_string(), !valid)];
+                } else if let Some(media) = line.strip_prefix("media:") {
+                    let mut parts = media.trim().splitn(2, ' ');
+                    let kind = parts.next().and_then(MediaKind::parse)
+                        .ok_or_else(|| ImportError::Syntax(lineno, "unknown media type".to_string()))?;
+                    let source = parts.next().map(str::trim).filter(|s| !s.is_empty())
+                        .ok_or_else(|| ImportError::Syntax(lineno, "media source is missing".to_string()))?;
+                    q.media = Some(Media { kind, source: source.to_string() });
+                } else {
+                    return Err(ImportError::Syntax(lineno, format!("unexpected line: {}", line)))
+                }
+            }
+        }
+    }
+    if let Some(q) = current.take() {
+        res.push(check(q, data.lines().count())?);
+    }
+    Ok(res)
+}
+
+fn check(quest: Question, lineno: usize) -> Result<Question, ImportError> {
+    if quest.answers.len() < 2 {
+        return Err(ImportError::Syntax(lineno, format!("\"{}\": at least two answers required", quest.text)))
+    }
+    let correct = quest.answers.iter().filter(|a| a.1).count();
+    if correct == 0 {
+        return Err(ImportError::Syntax(lineno, format!("\"{}\": no correct answer", quest.text)))
+    }
+    if correct > 1 && quest.kind != QuestionKind::Multiple {
+        return Err(ImportError::Syntax(lineno, format!("\"{}\": several correct answers, use type: multiple", quest.text)))
+    }
+    Ok(quest)
+}
+
+pub fn import<T: AsRef<Path>>(quester: &mut Quester, path: T) -> Result<usize, ImportError> {
+    let data = std::fs::read_to_string(path)?;
+    let quests = parse(&data)?;
+    for q in &quests {
+        quester.add_quest(q)?;
+    }
+    Ok(quests.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_answer_with_media() {
+        let quests = parse("# comment\nName this song\nmedia: audio ./songs/01.mp3\ncategory: music\n+ A\n- B\n").unwrap();
+        assert_eq!(quests.len(), 1);
+        assert_eq!(quests[0].kind, QuestionKind::Single);
+        assert_eq!(quests[0].category.as_deref(), Some("music"));
+        assert_eq!(quests[0].answers, vec![("A".to_string(), true), ("B".to_string(), false)]);
+        assert_eq!(quests[0].media.as_ref().map(|m| (m.kind, m.source.as_str())), Some((MediaKind::Audio, "./songs/01.mp3")));
+    }
+
+    #[test]
+    fn multiple_answers() {
+        let quests = parse("Primes?\ntype: multiple\n+ 2\n+ 3\n- 4\n\nOne?\n+ 1\n- 2").unwrap();
+        assert_eq!(quests.len(), 2);
+        assert_eq!(quests[0].kind, QuestionKind::Multiple);
+        assert_eq!(quests[0].answers.iter().filter(|a| a.1).count(), 2);
+        assert_eq!(quests[1].kind, QuestionKind::Single);
+    }
+
+    #[test]
+    fn several_correct_answers_need_multiple() {
+        assert!(matches!(parse("Primes?\n+ 2\n+ 3\n- 4\n"), Err(ImportError::Syntax(4, _))));
+    }
+
+    #[test]
+    fn true_false() {
+        let quests = parse("The sky is green\ntype: truefalse\nanswer: false\n").unwrap();
+        assert_eq!(quests[0].kind, QuestionKind::TrueFalse);
+        assert_eq!(quests[0].answers, vec![("true".to_string(), false), ("false".to_string(), true)]);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(matches!(parse("Q\ntype: essay\n+ a\n- b\n"), Err(ImportError::Syntax(2, _))));
+        assert!(matches!(parse("Q\ntype: truefalse\nanswer: maybe\n"), Err(ImportError::Syntax(3, _))));
+        assert!(matches!(parse("Q\nmedia: photo\n+ a\n- b\n"), Err(ImportError::Syntax(2, _))));
+        assert!(matches!(parse("Q\n+ a\n"), Err(ImportError::Syntax(_, _))));
+    }
+}
@@ -1,32 +1,190 @@
-use crate::quiz::{Poll};
+use crate::quiz::{Poll, Player};
 use telegram_bot::{UserId};
+use std::collections::HashMap;
 use crate::markdown;
+use crate::users::AnswerStats;
+use crate::quiz::anticheat::Evidence;
+use crate::quiz::quests::{Playlist, PlaylistItem};
+use crate::markdown::{MarkdownV2, Mentions, Text};
+use crate::i18n::{Lang, tr, tr_text};
+use crate::dispatcher::config::Setting;
 
-fn user_list(data: &Vec<(UserId, String)>) -> String {
+fn user_list(text: &mut Text<MarkdownV2>, data: &Vec<Player>, style: Mentions) {
     let mut f = false;
-    let mut text = String::new();
     for i in data {
-        if f { text += ", " }
-        text += &*markdown::bold(&*markdown::escape(&i.1));
+        if f { text.text(", "); }
+        text.user(i.id, &i.name, i.username.as_deref(), style);
         f = true;
     }
-    text
 }
 
-pub(crate) fn poll_result<'s>(poll: &Poll) -> String {
+fn tally(text: &mut Text<MarkdownV2>, poll: &Poll, tally: &telegram_bot::Poll) {
+    for (i, option) in tally.options.iter().enumerate() {
+        let mark = if poll.correct.contains(&(i as i32)) { "✅" } else { "▫️" };
+        text.text(&format!("{} {} — {}\n", mark, option.text, option.voter_count));
+    }
+}
+
+pub(crate) fn poll_result<'s>(lang: Lang, style: Mentions, poll: &Poll, stopped: Option<&telegram_bot::Poll>) -> Text<MarkdownV2> {
     if poll.correct_answers.len() == 0 && poll.incorrect_answers.len() == 0 {
-        markdown::escape("Никто не ответил:с
-        Ну и сами себе вопросы загадывайте!")
+        tr_text(lang, "quiz.no_answers", &[])
     } else {
-        let mut text = String::new();
+        let mut text = Text::markdown();
+        if let Some(stopped) = stopped {
+            tally(&mut text, poll, stopped);
+        }
         if poll.correct_answers.len() > 0 {
-            text += "Список Кодзим: ";
-            text += &user_list(&poll.correct_answers);
+            text.push(&tr_text(lang, "quiz.correct", &[]));
+            user_list(&mut text, &poll.correct_answers, style);
         };
         if poll.incorrect_answers.len() > 0 {
-            text += "\nСписок дэбилов: ";
-            text += &user_list(&poll.incorrect_answers);
+            text.text("\n");
+            text.push(&tr_text(lang, "quiz.incorrect", &[]));
+            user_list(&mut text, &poll.incorrect_answers, style);
         };
         text
     }
-}
\ No newline at end of file
+}
+
+fn mask(answer: &str, revealed: usize) -> String {
+    answer.chars().enumerate().map(|(i, c)| {
+        if i < revealed || !c.is_alphanumeric() { c } else { '_' }
+    }).collect()
+}
+
+pub(crate) fn hint(lang: Lang, name: &str, cost: i64, answer: &str, revealed: usize) -> Text<MarkdownV2> {
+    let mut bold_name = Text::markdown();
+    bold_name.bold(name);
+    let mut code = Text::markdown();
+    code.code(&mask(answer, revealed));
+    tr_text(lang, "quiz.hint", &[("name", (&bold_name).into()), ("cost", cost.into()), ("mask", (&code).into())])
+}
+
+pub(crate) fn hint_failed(lang: Lang) -> Text<MarkdownV2> {
+    tr_text(lang, "quiz.hint_failed", &[])
+}
+
+pub(crate) fn tidy_status(lang: Lang, enabled: bool) -> String {
+    tr(lang, if enabled { "quiz.tidy_on" } else { "quiz.tidy_off" }, &[])
+}
+
+pub(crate) fn chain_summary(lang: Lang, rounds: u32, scores: &HashMap<UserId, (String, i64)>) -> Text<MarkdownV2> {
+    let mut text = tr_text(lang, "quiz.stopped", &[]);
+    text.text(" ").push(&tr_text(lang, "quiz.rounds", &[("n", (rounds as i64).into())]));
+    let mut scores = scores.values().collect::<Vec<&(String, i64)>>();
+    scores.sort_by(|a, b| b.1.cmp(&a.1));
+    for (pos, (name, score)) in scores.into_iter().enumerate() {
+        text.text(&format!("\n{} ", pos + 1)).bold(name).text(&format!(": {}", score));
+    }
+    text
+}
+
+pub(crate) fn no_chain(lang: Lang) -> String {
+    tr(lang, "quiz.no_chain", &[])
+}
+
+pub(crate) fn paused(lang: Lang) -> String {
+    tr(lang, "quiz.paused", &[])
+}
+
+pub(crate) fn reading_time_status(lang: Lang, base: u64, per_char: u64) -> String {
+    tr(lang, "quiz.readtime", &[("base", (base as i64).into()), ("per_char", (per_char as i64).into())])
+}
+
+pub(crate) fn invalid_setting(lang: Lang, setting: &Setting) -> String {
+    tr(lang, "config.invalid", &[("key", setting.key.into()), ("expected", (&setting.kind.describe()).into())])
+}
+
+pub(crate) fn cheat_report(lang: Lang, suspects: &[(AnswerStats, Vec<Evidence>)]) -> Text<MarkdownV2> {
+    if suspects.is_empty() {
+        return tr_text(lang, "quiz.cheaters_none", &[])
+    }
+    let mut text = tr_text(lang, "quiz.cheaters", &[]);
+    for (stats, evidence) in suspects {
+        let mut name = Text::markdown();
+        name.bold(&markdown::full_name(&stats.user.first_name, &stats.user.last_name));
+        text.text("\n").push(&tr_text(lang, "quiz.cheater", &[
+            ("name", (&name).into()),
+            ("answers", stats.answers.into()),
+            ("correct", stats.correct.into())
+        ]));
+        for e in evidence {
+            let line = match e {
+                Evidence::TooFast(n) => tr_text(lang, "quiz.evidence_fast", &[("n", (*n).into()), ("latency", stats.min_latency.into())]),
+                Evidence::Superhuman { accuracy, latency, chat_latency } => tr_text(lang, "quiz.evidence_superhuman", &[
+                    ("accuracy", (*accuracy).into()),
+                    ("latency", (*latency).into()),
+                    ("chat_latency", (*chat_latency).into())
+                ]),
+            };
+            text.text("\n  • ").push(&line);
+        }
+    }
+    text
+}
+
+pub(crate) fn scoring_status(lang: Lang, partial: bool) -> String {
+    tr(lang, if partial { "quiz.scoring_partial" } else { "quiz.scoring_strict" }, &[])
+}
+
+pub(crate) fn playlist_finished(lang: Lang, rounds: u32, scores: &HashMap<UserId, (String, i64)>) -> Text<MarkdownV2> {
+    let mut text = tr_text(lang, "playlist.finished", &[]);
+    text.text("\n").push(&chain_summary(lang, rounds, scores));
+    text
+}
+
+pub(crate) fn no_questions(lang: Lang, rounds: u32, scores: &HashMap<UserId, (String, i64)>) -> Text<MarkdownV2> {
+    let mut text = tr_text(lang, "quiz.no_questions", &[]);
+    text.text("\n").push(&chain_summary(lang, rounds, scores));
+    text
+}
+
+pub(crate) fn send_failed(lang: Lang, rounds: u32, scores: &HashMap<UserId, (String, i64)>) -> Text<MarkdownV2> {
+    let mut text = tr_text(lang, "quiz.send_failed", &[]);
+    text.text("\n").push(&chain_summary(lang, rounds, scores));
+    text
+}
+
+pub(crate) fn playlist_not_found(lang: Lang) -> String {
+    tr(lang, "playlist.not_found", &[])
+}
+
+pub(crate) fn playlist_usage(lang: Lang) -> String {
+    tr(lang, "playlist.usage", &[])
+}
+
+pub(crate) fn playlist_created(lang: Lang, name: &str) -> String {
+    tr(lang, "playlist.created", &[("name", name.into())])
+}
+
+pub(crate) fn playlist_exists(lang: Lang, name: &str) -> String {
+    tr(lang, "playlist.exists", &[("name", name.into())])
+}
+
+pub(crate) fn playlist_item_added(lang: Lang, name: &str) -> String {
+    tr(lang, "playlist.added", &[("name", name.into())])
+}
+
+pub(crate) fn playlist_deleted(lang: Lang, name: &str) -> String {
+    tr(lang, "playlist.deleted", &[("name", name.into())])
+}
+
+pub(crate) fn playlist_list(lang: Lang, names: &[String]) -> String {
+    if names.is_empty() {
+        return tr(lang, "playlist.empty_list", &[])
+    }
+    tr(lang, "playlist.list", &[("names", (&names.join(", ")).into())])
+}
+
+pub(crate) fn playlist_info(lang: Lang, playlist: &Playlist, texts: &[Option<String>]) -> String {
+    let key = if playlist.shuffle { "playlist.shuffled" } else { "playlist.ordered" };
+    let mut text = tr(lang, key, &[("name", (&playlist.name).into())]);
+    for (pos, (item, quest)) in playlist.items.iter().zip(texts).enumerate() {
+        text += &match item {
+            PlaylistItem::Question(id) => format!("\n{}. #{} {}", pos + 1, id, quest.as_deref().unwrap_or("?")),
+            PlaylistItem::Category { name, weight } => format!("\n{}. {}", pos + 1,
+                tr(lang, "playlist.category", &[("name", name.into()), ("weight", (*weight as i64).into())])),
+        };
+    }
+    text
+}
@@ -1,48 +1,287 @@
-use crate::dispatcher::Subscriber;
+use crate::dispatcher::{Policy, Subscriber, Tasks};
+use crate::dispatcher::commands::{ArgKind, Command, CommandSpec, Invocation};
+use crate::dispatcher::config::{Config, Setting, Value};
 use std::collections::hash_map::RandomState;
 use tokio::sync::{mpsc, Mutex};
 use tokio::sync::mpsc::{Sender, Receiver};
 use std::collections::HashMap;
-use telegram_bot::{Message, Api, SendPoll, MessageId, ChatId, UserId, MessageOrChannelPost, MessageKind, SendMessage, ParseMode};
-use crate::dispatcher::types::{UpdateKind};
+use telegram_bot::{Message, Api, SendPoll, StopPoll, DeleteMessage, MessageId, ChatId, UserId, MessageOrChannelPost, MessageKind, SendMessage};
+use crate::dispatcher::types::{self, UpdateKind};
 use tokio::stream::StreamExt;
 use std::sync::Arc;
 use tokio::time::{Instant, Duration};
-use crate::users::{Users, User};
-use std::env;
-use crate::quiz::quests::Quester;
-use crate::quiz::messages::poll_result;
-use crate::utils::must_send;
+use crate::users::{Users, User, Answer};
+use std::path::Path;
+use crate::quiz::quests::{Quester, Question, Playlist, PlaylistItem};
+use crate::quiz::messages::{poll_result, hint, hint_failed, tidy_status, chain_summary, no_chain, paused, reading_time_status, cheat_report, scoring_status,
+                           playlist_finished, no_questions, send_failed, playlist_not_found, playlist_created, playlist_exists, playlist_item_added,
+                           playlist_info, playlist_deleted, playlist_list, playlist_usage, invalid_setting};
+use crate::quiz::anticheat::ReadingTime;
+use crate::quiz::state::{State, StoredPoll, StoredAnswer, StoredChain};
+use crate::utils::{must_send, send_text};
+use crate::i18n;
+use crate::markdown;
 
 pub mod quests;
+pub mod import;
+mod state;
+mod anticheat;
 mod utils;
 mod messages;
 
+/// User who answered the poll
+#[derive(Debug, Clone)]
+pub(super) struct Player {
+    id: UserId,
+    name: String,
+    username: Option<String>,
+}
+
 #[derive(Debug)]
 pub(super) struct Poll {
     id: String,
     chat: ChatId,
     message_id: MessageId,
 
-    correct_answers: Vec<(UserId, String)>,
-    incorrect_answers: Vec<(UserId, String)>,
-    correct_answer: i32,
+    correct_answers: Vec<Player>,
+    incorrect_answers: Vec<Player>,
+    correct: Vec<i32>,
+    // Regular poll with multiple answers allowed
+    multiple: bool,
+    // Text of the correct answer for hints, empty for multiple answer polls
+    correct_text: String,
+    // Length of the question and all options, used to estimate the reading time
+    text_len: usize,
+
+    hints: HashMap<UserId, u32>,
+    revealed: usize,
+
+    // Media and other service messages sent along with the poll
+    messages: Vec<MessageId>,
+
+    start: Instant,
+    created: i64,
+}
+
+const CORRECT_SCORE: i64 = 2;
+const HINTED_SCORE: i64 = 1;
+const INCORRECT_SCORE: i64 = -1;
+const HINT_COST: i64 = 1;
+/// Questions which failed to send in a row before the chain is ended
+const MAX_SEND_FAILURES: u32 = 3;
+/// Delay after a failed question, multiplied by the number of failures in a row
+const SEND_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Role allowed to configure the quiz and view playlists
+const QUIZ_MASTER: &str = "quizmaster";
+/// Role allowed to look for cheaters
+const MODERATOR: &str = "moderator";
+
+impl Poll {
+    fn stored(&self) -> StoredPoll {
+        StoredPoll {
+            id: self.id.clone(),
+            chat_id: i64::from(self.chat),
+            message_id: i64::from(self.message_id),
+            correct: self.correct.clone(),
+            multiple: self.multiple,
+            correct_text: self.correct_text.clone(),
+            text_len: self.text_len,
+            revealed: self.revealed,
+            start: self.created,
+            answers: vec!(),
+            hints: vec!(),
+        }
+    }
+
+    fn restore(stored: StoredPoll, duration: u64) -> Poll {
+        let elapsed = std::cmp::min(std::cmp::max(state::now() - stored.start, 0) as u64, duration);
+        let mut poll = Poll {
+            id: stored.id,
+            chat: ChatId::new(stored.chat_id),
+            message_id: MessageId::new(stored.message_id),
+            correct_answers: vec!(),
+            incorrect_answers: vec!(),
+            correct: stored.correct,
+            multiple: stored.multiple,
+            correct_text: stored.correct_text,
+            text_len: stored.text_len,
+            hints: stored.hints.into_iter().map(|(uid, n)| (UserId::new(uid), n)).collect(),
+            revealed: stored.revealed,
+            // The monotonic clock may have started less than `elapsed` ago, right after a reboot
+            start: Instant::now().checked_sub(Duration::from_secs(elapsed)).unwrap_or_else(Instant::now),
+            messages: vec!(),
+            created: stored.start,
+        };
+        for answer in stored.answers {
+            let player = Player { id: UserId::new(answer.uid), name: answer.name, username: answer.username };
+            if answer.correct {
+                poll.correct_answers.push(player);
+            } else {
+                poll.incorrect_answers.push(player);
+            }
+        }
+        poll
+    }
+}
 
-    start: Instant
+/// Sequence of polls started by one `/quiz` command
+struct Chain {
+    starter: UserId,
+    paused: bool,
+    rounds: u32,
+    scores: HashMap<UserId, (String, i64)>,
+    playlist: Option<Playlist>,
+    // Number of playlist items consumed
+    position: usize,
+    // Questions asked in this chain
+    used: Vec<i64>,
+}
+
+impl Chain {
+    fn stored(&self, chat: ChatId) -> StoredChain {
+        StoredChain {
+            chat_id: i64::from(chat),
+            starter: i64::from(self.starter),
+            paused: self.paused,
+            rounds: self.rounds,
+            playlist: self.playlist.as_ref().map(|p| p.name.clone()),
+            position: self.position,
+            used: self.used.clone(),
+            scores: vec!(),
+        }
+    }
+
+    fn new(starter: UserId, playlist: Option<Playlist>) -> Chain {
+        Chain {
+            starter,
+            paused: false,
+            rounds: 0,
+            scores: HashMap::new(),
+            playlist,
+            position: 0,
+            used: vec!(),
+        }
+    }
+
+    /// Picks the next question, `None` means the playlist is over or there are no questions at all
+    fn next_quest(&mut self, quests: &Quester) -> Option<Question> {
+        let playlist = match &self.playlist {
+            Some(playlist) => playlist,
+            None => {
+                let quest = quests.get_quest().unwrap()?;
+                self.used.push(quest.id);
+                return Some(quest)
+            }
+        };
+        // Deleted questions and categories without unused questions are not drawn again
+        let mut skipped = self.used.clone();
+        let mut exhausted = vec!();
+        loop {
+            let quest = match playlist.next_item(self.position, &skipped, &exhausted)? {
+                PlaylistItem::Question(id) => {
+                    skipped.push(*id);
+                    quests.quest(*id).unwrap()
+                }
+                PlaylistItem::Category { name, .. } => {
+                    let quest = quests.category_quest(name, &self.used).unwrap();
+                    if quest.is_none() {
+                        exhausted.push(name.clone());
+                    }
+                    quest
+                }
+            };
+            self.position += 1;
+            if let Some(quest) = quest {
+                self.used.push(quest.id);
+                return Some(quest)
+            }
+        }
+    }
 }
 
 struct PollList {
     counts: HashMap<ChatId, u64>,
-    polls: HashMap<String, Poll>
+    chains: HashMap<ChatId, Chain>,
+    polls: HashMap<String, Poll>,
+    // Messages of the previous rounds, removed in tidy mode
+    trash: HashMap<ChatId, Vec<MessageId>>,
+    state: State,
+    config: Config,
+}
+
+impl PollList {
+    fn insert(&mut self, poll: Poll) {
+        self.state.add_poll(&poll.stored()).unwrap();
+        self.polls.insert(poll.id.clone(), poll);
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Poll> {
+        let poll = self.polls.remove(id)?;
+        self.state.remove_poll(id).unwrap();
+        Some(poll)
+    }
+
+    fn start_chain(&mut self, chat: ChatId, chain: Chain) {
+        self.state.set_chain(&chain.stored(chat)).unwrap();
+        self.counts.insert(chat, 1);
+        self.chains.insert(chat, chain);
+    }
+
+    fn save_chain(&mut self, chat: ChatId) {
+        if let Some(chain) = self.chains.get(&chat) {
+            self.state.set_chain(&chain.stored(chat)).unwrap();
+        }
+    }
+
+    fn end_chain(&mut self, chat: ChatId) -> Option<Chain> {
+        self.state.remove_chain(i64::from(chat)).unwrap();
+        self.counts.insert(chat, 0);
+        self.chains.remove(&chat)
+    }
+
+    fn active_poll(&self, chat: ChatId) -> Option<String> {
+        self.polls.values().find(|p| p.chat == chat).map(|p| p.id.clone())
+    }
+
+    /// Moves the running quiz of a group to its supergroup, the stored one was moved by `migrate_chat`
+    fn migrate(&mut self, from: ChatId, to: ChatId) {
+        if let Some(chain) = self.chains.remove(&from) {
+            self.chains.entry(to).or_insert(chain);
+        }
+        if let Some(count) = self.counts.remove(&from) {
+            self.counts.entry(to).or_insert(count);
+        }
+        // Messages of the group can't be deleted from the supergroup
+        self.trash.remove(&from);
+        for poll in self.polls.values_mut().filter(|p| p.chat == from) {
+            poll.chat = to;
+        }
+    }
+
+    fn duration(&self, chat: ChatId) -> Duration {
+        Duration::from_secs(self.config.get(chat, "round_seconds").as_integer() as u64)
+    }
+}
+
+fn reading_time(config: &Config, chat: ChatId) -> ReadingTime {
+    let get = |key: &str| config.get(chat, key).as_integer() as u64;
+    ReadingTime {
+        base: get("readtime_base"),
+        per_char: get("readtime_per_char"),
+    }
 }
 
 pub struct QuizModule {
-    command: Sender<Message>,
+    command: Sender<Invocation>,
+    hint: Sender<Invocation>,
+    playlist: Sender<Invocation>,
     poll: Sender<telegram_bot::UpdateKind>,
+    migrate: Sender<Message>,
 }
 
 impl QuizModule {
     async fn timer_loop(api: Api, quests: Arc<Mutex<Quester>>, polls: Arc<Mutex<PollList>>) {
+        QuizModule::restore(&api, &quests, &polls).await;
         loop {
             tokio::time::delay_for(Duration::from_secs(2)).await;
             let mut removed_polls = vec!();
@@ -50,93 +289,243 @@ impl QuizModule {
                 let mut polls = polls.lock().await;
                 let mut remove_ids = vec!();
                 for (id, poll) in polls.polls.iter() {
-                    if poll.start.elapsed() > Duration::from_secs(15) {
+                    if poll.start.elapsed() > polls.duration(poll.chat) {
                         remove_ids.push(id.clone());
                     }
                 }
 
                 for i in remove_ids {
-                    let t = polls.polls.remove(&i).unwrap();
-                    let answers = t.correct_answers.len() + t.incorrect_answers.len();
-                    let chat = t.chat.clone();
-
-                    removed_polls.push(t);
-
-                    if answers == 0 {
-                        match polls.counts.get_mut(&chat) {
-                            Some(cnt) => {
-                                if *cnt == 1 {
-                                    *cnt = 0;
-                                    continue
-                                }
+                    let t = polls.remove(&i).unwrap();
+                    let next = if t.correct_answers.len() + t.incorrect_answers.len() == 0 {
+                        polls.end_chain(t.chat);
+                        false
+                    } else {
+                        match polls.chains.get_mut(&t.chat) {
+                            Some(chain) => {
+                                chain.rounds += 1;
+                                !chain.paused
                             }
-                            _ => {}
+                            None => false
                         }
-                    } else {
-                        continue
-                    }
-                    unreachable!("Polls counter corrupted")
+                    };
+                    polls.save_chain(t.chat);
+                    removed_polls.push((t, next));
                 }
             }
 
-            for poll in removed_polls {
-                let mut msg = SendMessage::new(poll.chat, poll_result(&poll));
-                msg.parse_mode(ParseMode::MarkdownV2);
-                must_send(&api, msg).await;
-
-                if poll.correct_answers.len() + poll.incorrect_answers.len() != 0 {
-                    loop {
-                        let quest = quests.lock().await.get_quest().unwrap();
-                        match QuizModule::create_poll(&api, poll.chat, quest).await {
-                            Some(p) => {
-                                let mut polls = polls.lock().await;
-                                polls.polls.insert(p.id.clone(), p);
-                                break
-                            }
-                            None => {}
+            for (poll, next) in removed_polls {
+                QuizModule::close_poll(&api, &polls, &poll).await;
+                if next {
+                    QuizModule::start_poll(&api, &quests, &polls, poll.chat).await;
+                }
+            }
+        }
+    }
+
+    /// Stops the poll and posts its results
+    async fn close_poll(api: &Api, polls: &Arc<Mutex<PollList>>, poll: &Poll) {
+        let tally = must_send(api, StopPoll::new(poll.chat, poll.message_id)).await;
+
+        let text = poll_result(i18n::lang(poll.chat), markdown::mentions(poll.chat), poll, tally.as_ref());
+        let result = send_text(api, poll.chat, &text, Some(poll.message_id)).await;
+
+        let tidy = polls.lock().await.config.get(poll.chat, "tidy").as_bool();
+        if tidy {
+            let old = {
+                let mut list = polls.lock().await;
+                let mut current = poll.messages.clone();
+                current.push(poll.message_id);
+                current.extend(result);
+                list.trash.insert(poll.chat, current).unwrap_or_default()
+            };
+            for id in old {
+                must_send(api, DeleteMessage::new(poll.chat, id)).await;
+            }
+        }
+    }
+
+    /// Loads the polls which were running before restart. Expired ones are closed by the timer loop,
+    /// chains interrupted between two polls are resumed.
+    async fn restore(api: &Api, quests: &Arc<Mutex<Quester>>, polls: &Arc<Mutex<PollList>>) {
+        let resumed = {
+            let quests = quests.lock().await;
+            let mut list = polls.lock().await;
+            let list = &mut *list;
+            let stored = list.state.polls().unwrap();
+            let chains = list.state.chains().unwrap();
+            for p in stored {
+                let duration = list.duration(ChatId::new(p.chat_id)).as_secs();
+                let poll = Poll::restore(p, duration);
+                list.polls.insert(poll.id.clone(), poll);
+            }
+            let mut resumed = vec!();
+            for c in chains {
+                let chat = ChatId::new(c.chat_id);
+                if !c.paused && list.active_poll(chat).is_none() {
+                    resumed.push(chat);
+                }
+                let playlist = c.playlist.and_then(|name| quests.playlist(&name).unwrap());
+                list.counts.insert(chat, 1);
+                list.chains.insert(chat, Chain {
+                    paused: c.paused,
+                    rounds: c.rounds,
+                    position: c.position,
+                    used: c.used,
+                    scores: c.scores.into_iter().map(|(uid, name, score)| (UserId::new(uid), (name, score))).collect(),
+                    ..Chain::new(UserId::new(c.starter), playlist)
+                });
+            }
+            resumed
+        };
+
+        for chat in resumed {
+            QuizModule::start_poll(api, quests, polls, chat).await;
+        }
+    }
+
+    /// Asks the next question of the chain unless it was stopped or paused meanwhile
+    async fn start_poll(api: &Api, quests: &Arc<Mutex<Quester>>, polls: &Arc<Mutex<PollList>>, chat: ChatId) {
+        let mut failures = 0;
+        loop {
+            let quest = {
+                let quests = quests.lock().await;
+                let mut list = polls.lock().await;
+                if list.active_poll(chat).is_some() {
+                    return
+                }
+                match list.chains.get_mut(&chat) {
+                    Some(chain) if !chain.paused => chain.next_quest(&quests),
+                    _ => return
+                }
+            };
+            let quest = match quest {
+                Some(quest) => quest,
+                None => {
+                    let chain = polls.lock().await.end_chain(chat);
+                    if let Some(chain) = chain {
+                        let text = match chain.playlist {
+                            Some(_) => playlist_finished(i18n::lang(chat), chain.rounds, &chain.scores),
+                            None => no_questions(i18n::lang(chat), chain.rounds, &chain.scores),
+                        };
+                        send_text(api, chat, &text, None).await;
+                    }
+                    break
+                }
+            };
+            polls.lock().await.save_chain(chat);
+            match QuizModule::create_poll(api, chat, quest).await {
+                Some(p) => {
+                    let mut list = polls.lock().await;
+                    if list.chains.contains_key(&chat) {
+                        list.insert(p);
+                    } else {
+                        // The quiz was stopped while the poll was being sent
+                        drop(list);
+                        must_send(api, StopPoll::new(chat, p.message_id)).await;
+                    }
+                    break
+                }
+                // The question is used by now, the next attempt takes another one
+                None => {
+                    failures += 1;
+                    if failures >= MAX_SEND_FAILURES {
+                        let chain = polls.lock().await.end_chain(chat);
+                        if let Some(chain) = chain {
+                            send_text(api, chat, &send_failed(i18n::lang(chat), chain.rounds, &chain.scores), None).await;
                         }
+                        break
                     }
+                    tokio::time::delay_for(SEND_RETRY_DELAY * failures).await;
                 }
             }
         }
     }
 
     async fn create_poll(api: &Api, chat: ChatId, quest: quests::Question) -> Option<Poll> {
+        let mut messages = vec!();
+        if let Some(media) = &quest.media {
+            if let MessageOrChannelPost::Message(m) = utils::send_media(api, chat, media).await? {
+                messages.push(m.id);
+            }
+        }
+
+        // True/false options are stored in fixed order and labeled in the chat language
+        let answers = match quest.kind {
+            quests::QuestionKind::TrueFalse => quest.answers.into_iter().zip(&["quiz.true", "quiz.false"])
+                .map(|((_, valid), key)| (i18n::tr(i18n::lang(chat), key, &[]), valid))
+                .collect::<Vec<(String, bool)>>(),
+            _ => quest.answers,
+        };
+        let text_len = quest.text.chars().count() + answers.iter().map(|a| a.0.chars().count()).sum::<usize>();
         let empty = Vec::<String>::new();
         let mut poll = SendPoll::new(chat, quest.text, empty);
-        poll.quiz();
+        let multiple = quest.kind == quests::QuestionKind::Multiple;
+        if multiple {
+            poll.regular();
+            poll.allows_multiple_answers();
+        } else {
+            poll.quiz();
+        }
         poll.not_anonymous();
-        for (i, (text, correct)) in quest.answers.into_iter().enumerate() {
-            poll.add_option(text);
-            if correct {
-                poll.correct_option_id(i as i64);
+        let mut correct = vec!();
+        let mut correct_text = String::new();
+        for (i, (text, valid)) in answers.into_iter().enumerate() {
+            if valid {
+                if !multiple {
+                    poll.correct_option_id(i as i64);
+                    correct_text = text.clone();
+                }
+                correct.push(i as i32);
             };
+            poll.add_option(text);
         }
 
-        // Fuck the telegram api, it doesn't send poll update if if was closed by open_period or close_date
-        poll.open_period(15);
-
-        if let MessageOrChannelPost::Message(Message { id, kind: MessageKind::Poll { data: telegram_bot::Poll { id: poll_id, correct_option_id: Some(correct), ..} }, ..}) = api.send(poll).await.unwrap() {
+        if let MessageOrChannelPost::Message(Message { id, kind: MessageKind::Poll { data: telegram_bot::Poll { id: poll_id, ..} }, ..}) = must_send(api, poll).await? {
             Some(Poll {
                 id: poll_id,
                 correct_answers: vec![],
                 incorrect_answers: vec![],
                 chat,
                 message_id: id,
-                correct_answer: correct as i32,
-                start: Instant::now()
+                correct,
+                multiple,
+                correct_text,
+                text_len,
+                hints: HashMap::new(),
+                revealed: 0,
+                start: Instant::now(),
+                messages,
+                created: state::now(),
             })
         } else {
             unreachable!("Invalid message received")
         }
     }
 
+    async fn migrate_loop(mut events: Receiver<Message>, polls: Arc<Mutex<PollList>>) {
+        while let Some(msg) = events.next().await {
+            let (from, to) = match msg.kind {
+                MessageKind::MigrateToChatId { data } => (msg.chat.id(), ChatId::new(data)),
+                MessageKind::MigrateFromChatId { data } => (ChatId::new(data), msg.chat.id()),
+                _ => continue
+            };
+            polls.lock().await.migrate(from, to);
+        }
+    }
+
     async fn poll_loop(mut events: Receiver<telegram_bot::UpdateKind>, users: Arc<Mutex<Users>>, list: Arc<Mutex<PollList>>) {
         while let Some(update) = events.next().await {
             match &update {
                 telegram_bot::UpdateKind::PollAnswer(telegram_bot::PollAnswer { poll_id, user, option_ids }) => {
                     let mut l = list.lock().await;
+                    let l = &mut *l;
                     if let Some(poll) = l.polls.get_mut(poll_id.as_str()) {
+                        // Votes in regular polls may be retracted and cast again
+                        let answered = poll.correct_answers.iter().chain(poll.incorrect_answers.iter())
+                            .any(|p| p.id == user.id);
+                        if option_ids.is_empty() || answered {
+                            continue
+                        }
                         let last_name = match user.last_name.as_ref() {
                             Some(last_name) => {
                                 last_name.clone()
@@ -158,7 +547,7 @@ impl QuizModule {
                                 uid: i64::from(user.id),
                                 first_name: user.first_name.clone(),
                                 last_name: last_name.clone(),
-                                username
+                                username: username.clone()
                             }).unwrap();
                         }
                         let name = match last_name.len() {
@@ -169,12 +558,52 @@ impl QuizModule {
                                 format!("{} {}", &user.first_name, last_name)
                             }
                         };
-                        if poll.correct_answer == option_ids[0] as i32 {
-                            users.lock().await.inc_rating(i64::from(user.id), 2).unwrap();
-                            poll.correct_answers.push((user.id, name))
+                        let hints = poll.hints.get(&user.id).cloned().unwrap_or_default();
+                        let chosen = option_ids.iter().map(|o| *o as i32).collect::<Vec<i32>>();
+                        let hits = chosen.iter().filter(|o| poll.correct.contains(o)).count() as i64;
+                        let misses = chosen.len() as i64 - hits;
+                        let correct = misses == 0 && hits == poll.correct.len() as i64;
+                        let latency = poll.start.elapsed();
+                        let flagged = latency < reading_time(&l.config, poll.chat).min_reading_time(poll.text_len);
+                        let partial = poll.multiple && l.config.get(poll.chat, "scoring").as_str() == "partial";
+                        // Hints are shown to the whole chat, whoever answers after one did not pay for it
+                        let hinted = hints > 0 || poll.revealed > 0;
+                        let delta = match (correct, hinted, flagged) {
+                            (true, _, true) => 0,
+                            (true, false, _) => CORRECT_SCORE,
+                            (true, true, _) => HINTED_SCORE,
+                            (false, _, true) if partial => 0,
+                            (false, _, _) if partial => std::cmp::max(
+                                CORRECT_SCORE * (hits - misses) / poll.correct.len() as i64,
+                                INCORRECT_SCORE
+                            ),
+                            (false, _, _) => INCORRECT_SCORE,
+                        };
+                        users.lock().await.add_answer(&Answer {
+                            chat_id: i64::from(poll.chat),
+                            uid: i64::from(user.id),
+                            poll_id: &poll.id,
+                            delta,
+                            correct,
+                            hints,
+                            latency: latency.as_millis() as i64,
+                            flagged
+                        }).unwrap();
+                        l.state.add_answer(&poll.id, &StoredAnswer {
+                            uid: i64::from(user.id),
+                            name: name.clone(),
+                            username: user.username.clone(),
+                            correct
+                        }).unwrap();
+                        if let Some(chain) = l.chains.get_mut(&poll.chat) {
+                            chain.scores.entry(user.id).or_insert((name.clone(), 0)).1 += delta;
+                            l.state.add_chain_score(i64::from(poll.chat), i64::from(user.id), &name, delta).unwrap();
+                        }
+                        let player = Player { id: user.id, name, username: user.username.clone() };
+                        if correct {
+                            poll.correct_answers.push(player)
                         } else {
-                            users.lock().await.inc_rating(i64::from(user.id), -1).unwrap();
-                            poll.incorrect_answers.push((user.id, name))
+                            poll.incorrect_answers.push(player)
                         }
                     }
                 }
@@ -185,60 +614,317 @@ impl QuizModule {
 
 
 
-    async fn quiz_handler(mut events: Receiver<Message>, api: Api, polls: Arc<Mutex<PollList>>, quests: Arc<Mutex<Quester>>) {
-        while let Some(msg) = events.next().await {
+    async fn quiz_handler(mut events: Receiver<Invocation>, api: Api, polls: Arc<Mutex<PollList>>, quests: Arc<Mutex<Quester>>, users: Arc<Mutex<Users>>) {
+        while let Some(invocation) = events.next().await {
+            let msg = &invocation.message;
+            match invocation.command {
+                "quiz tidy" => {
+                    QuizModule::tidy(&api, &polls, msg, invocation.word("value") == Some("on")).await;
+                    continue
+                }
+                "quiz readtime" => {
+                    QuizModule::readtime(&api, &polls, msg, invocation.integer("base"), invocation.integer("per_char")).await;
+                    continue
+                }
+                "quiz scoring" => {
+                    QuizModule::scoring(&api, &polls, msg, invocation.word("mode").unwrap()).await;
+                    continue
+                }
+                "quiz cheaters" => {
+                    QuizModule::cheaters(&api, &users, msg).await;
+                    continue
+                }
+                action @ "quiz stop" | action @ "quiz pause" | action @ "quiz resume" => {
+                    QuizModule::control(&api, &polls, &quests, msg, invocation.is_admin, action).await;
+                    continue
+                }
+                _ => {}
+            }
+
+            let playlist = match invocation.word("playlist") {
+                Some(name) => match quests.lock().await.playlist(name).unwrap() {
+                    Some(playlist) => Some(playlist),
+                    None => {
+                        must_send(&api, SendMessage::new(msg.chat.id(), playlist_not_found(i18n::lang(msg.chat.id())))).await;
+                        continue
+                    }
+                },
+                _ => None
+            };
+
             {
                 let mut m = polls.lock().await;
-                match m.counts.get_mut(&msg.chat.id()) {
-                    Some(cnt) => {
-                        if *cnt > 0 {
-                            continue
+                if m.counts.get(&msg.chat.id()).cloned().unwrap_or_default() > 0 {
+                    continue
+                }
+                m.start_chain(msg.chat.id(), Chain::new(msg.from.id, playlist));
+            }
+
+            QuizModule::start_poll(&api, &quests, &polls, msg.chat.id()).await;
+        }
+    }
+
+    /// Handles `/quiz stop`, `/quiz pause` and `/quiz resume`
+    async fn control(api: &Api, polls: &Arc<Mutex<PollList>>, quests: &Arc<Mutex<Quester>>, msg: &Message, is_admin: bool, action: &str) {
+        let chat = msg.chat.id();
+        let starter = match polls.lock().await.chains.get(&chat) {
+            Some(chain) => chain.starter,
+            None => {
+                must_send(api, SendMessage::new(chat, no_chain(i18n::lang(chat)))).await;
+                return
+            }
+        };
+        if starter != msg.from.id && !is_admin {
+            return
+        }
+
+        match action {
+            "quiz stop" => {
+                let (poll, chain) = {
+                    let mut list = polls.lock().await;
+                    let poll = list.active_poll(chat).and_then(|id| list.remove(&id));
+                    (poll, list.end_chain(chat))
+                };
+                if let Some(poll) = poll {
+                    QuizModule::close_poll(api, polls, &poll).await;
+                }
+                if let Some(chain) = chain {
+                    send_text(api, chat, &chain_summary(i18n::lang(chat), chain.rounds, &chain.scores), None).await;
+                }
+            }
+            "quiz pause" => {
+                let mut list = polls.lock().await;
+                if let Some(chain) = list.chains.get_mut(&chat) {
+                    chain.paused = true;
+                }
+                list.save_chain(chat);
+                drop(list);
+                must_send(api, SendMessage::new(chat, paused(i18n::lang(chat)))).await;
+            }
+            _ => {
+                let start = {
+                    let mut list = polls.lock().await;
+                    let was_paused = match list.chains.get_mut(&chat) {
+                        Some(chain) => std::mem::replace(&mut chain.paused, false),
+                        None => false
+                    };
+                    list.save_chain(chat);
+                    was_paused && list.active_poll(chat).is_none()
+                };
+                if start {
+                    QuizModule::start_poll(api, quests, polls, chat).await;
+                }
+            }
+        }
+    }
+
+    async fn readtime(api: &Api, polls: &Arc<Mutex<PollList>>, msg: &Message, base: Option<i64>, per_char: Option<i64>) {
+        let chat = msg.chat.id();
+        let list = polls.lock().await;
+        if let (Some(base), Some(per_char)) = (base, per_char) {
+            let values = [("readtime_base", base), ("readtime_per_char", per_char)];
+            // Both values are checked first, so an invalid one doesn't leave the other changed alone
+            let settings = <QuizModule as Subscriber>::settings();
+            let invalid = values.iter()
+                .filter_map(|(key, value)| settings.iter().find(|s| s.key == *key).map(|s| (s, value)))
+                .find(|(setting, value)| setting.kind.parse(&value.to_string()).is_none());
+            if let Some((setting, _)) = invalid {
+                drop(list);
+                must_send(api, SendMessage::new(chat, invalid_setting(i18n::lang(chat), setting))).await;
+                return
+            }
+            for (key, value) in &values {
+                if let Err(e) = list.config.set(chat, key, Value::Integer(*value)) {
+                    println!("Quiz {} of {}: {:?}", key, i64::from(chat), e);
+                }
+            }
+        }
+        let current = reading_time(&list.config, chat);
+        drop(list);
+        must_send(api, SendMessage::new(chat, reading_time_status(i18n::lang(chat), current.base, current.per_char))).await;
+    }
+
+    async fn cheaters(api: &Api, users: &Arc<Mutex<Users>>, msg: &Message) {
+        let chat = msg.chat.id();
+        let stats = users.lock().await.answer_stats(i64::from(chat));
+        match stats {
+            Ok(stats) => {
+                send_text(api, chat, &cheat_report(i18n::lang(chat), &anticheat::suspects(stats)), None).await;
+            }
+            Err(e) => {
+                println!("Answer stats of {}: {:?}", i64::from(chat), e);
+                must_send(api, SendMessage::new(chat, i18n::tr(i18n::lang(chat), "quiz.cheaters_failed", &[]))).await;
+            }
+        }
+    }
+
+    async fn scoring(api: &Api, polls: &Arc<Mutex<PollList>>, msg: &Message, mode: &str) {
+        let chat = msg.chat.id();
+        let partial = {
+            let list = polls.lock().await;
+            if let Err(e) = list.config.set(chat, "scoring", Value::Word(mode.to_string())) {
+                println!("Quiz scoring of {}: {:?}", i64::from(chat), e);
+            }
+            // The reply tells what is in effect, the old mode if saving failed
+            list.config.get(chat, "scoring").as_str() == "partial"
+        };
+        must_send(api, SendMessage::new(chat, scoring_status(i18n::lang(chat), partial))).await;
+    }
+
+    async fn tidy(api: &Api, polls: &Arc<Mutex<PollList>>, msg: &Message, enabled: bool) {
+        let chat = msg.chat.id();
+        polls.lock().await.config.set(chat, "tidy", Value::Bool(enabled)).unwrap();
+        must_send(api, SendMessage::new(chat, tidy_status(i18n::lang(chat), enabled))).await;
+    }
+
+    /// Playlist management: `/playlist new <name> [shuffle]`, `/playlist add <name> <question id>`,
+    /// `/playlist add <name> category <category> [weight]`, `/playlist show <name>`, `/playlist delete <name>`
+    async fn playlist_handler(mut events: Receiver<Invocation>, api: Api, quests: Arc<Mutex<Quester>>) {
+        while let Some(invocation) = events.next().await {
+            let chat = invocation.message.chat.id();
+            let lang = i18n::lang(chat);
+            let text = {
+                let mut quests = quests.lock().await;
+                match (invocation.command, invocation.word("name")) {
+                    ("playlist new", Some(name)) => {
+                        if quests.create_playlist(name, invocation.word("order") == Some("shuffle")).unwrap() {
+                            playlist_created(lang, name)
                         } else {
-                            *cnt += 1
+                            playlist_exists(lang, name)
                         }
                     }
-                    None => {
-                        m.counts.insert(msg.chat.id(), 1);
+                    ("playlist add", Some(name)) => {
+                        let item = invocation.text("item").unwrap_or_default().split_whitespace().collect::<Vec<&str>>();
+                        let item = match item.as_slice() {
+                            [id] => id.parse::<i64>().ok().map(PlaylistItem::Question),
+                            ["category", category] => Some(PlaylistItem::Category { name: category.to_string(), weight: 1 }),
+                            ["category", category, weight] => weight.parse::<u32>().ok()
+                                .map(|weight| PlaylistItem::Category { name: category.to_string(), weight }),
+                            _ => None
+                        };
+                        match (quests.playlist(name).unwrap(), item) {
+                            (None, _) => playlist_not_found(lang),
+                            (Some(_), None) => playlist_usage(lang),
+                            (Some(_), Some(item)) => {
+                                quests.add_to_playlist(name, &item).unwrap();
+                                playlist_item_added(lang, name)
+                            }
+                        }
+                    }
+                    ("playlist show", Some(name)) => match quests.playlist(name).unwrap() {
+                        Some(playlist) => {
+                            let texts = playlist.items.iter().map(|i| match i {
+                                PlaylistItem::Question(id) => quests.quest(*id).unwrap().map(|q| q.text),
+                                PlaylistItem::Category { .. } => None,
+                            }).collect::<Vec<Option<String>>>();
+                            playlist_info(lang, &playlist, &texts)
+                        }
+                        None => playlist_not_found(lang)
+                    },
+                    ("playlist delete", Some(name)) => {
+                        quests.remove_playlist(name).unwrap();
+                        playlist_deleted(lang, name)
                     }
+                    _ => playlist_list(lang, &quests.playlists().unwrap())
                 }
-            }
+            };
+            must_send(&api, SendMessage::new(chat, text)).await;
+        }
+    }
 
-            loop {
-                let quest = quests.lock().await.get_quest().unwrap();
-                match QuizModule::create_poll(&api, msg.chat.id(), quest).await {
-                    Some(p) => {
-                        let mut polls = polls.lock().await;
-                        polls.polls.insert(p.id.clone(), p);
-                        break
-                    }
-                    None => {}
+    async fn hint_handler(mut events: Receiver<Invocation>, api: Api, polls: Arc<Mutex<PollList>>, users: Arc<Mutex<Users>>) {
+        while let Some(Invocation { message: msg, .. }) = events.next().await {
+            let text = {
+                let mut list = polls.lock().await;
+                let list = &mut *list;
+                let poll = match list.polls.values_mut().find(|p| p.chat == msg.chat.id()) {
+                    Some(poll) => poll,
+                    None => continue
+                };
+                let answered = poll.correct_answers.iter().chain(poll.incorrect_answers.iter())
+                    .any(|p| p.id == msg.from.id);
+                let len = poll.correct_text.chars().count();
+                if answered || poll.revealed + 1 >= len {
+                    continue
                 }
-            }
 
+                let bought = users.lock().await.buy_hint(i64::from(poll.chat), i64::from(msg.from.id), &poll.id, HINT_COST);
+                if let Err(e) = bought {
+                    println!("Hint for {} in {}: {:?}", i64::from(msg.from.id), i64::from(poll.chat), e);
+                    hint_failed(i18n::lang(poll.chat))
+                } else {
+                    let hints = poll.hints.entry(msg.from.id).or_insert(0);
+                    *hints += 1;
+                    poll.revealed = std::cmp::min(poll.revealed + (len + 3) / 4, len - 1);
+                    // Only a restart loses it, the hint is paid for already
+                    if let Err(e) = list.state.set_hints(&poll.id, i64::from(msg.from.id), *hints, poll.revealed) {
+                        println!("Hints of poll {}: {:?}", poll.id, e);
+                    }
+
+                    let name = crate::markdown::full_name(&msg.from.first_name, msg.from.last_name.as_deref().unwrap_or(""));
+                    hint(i18n::lang(poll.chat), &name, HINT_COST, &poll.correct_text, poll.revealed)
+                }
+            };
+            send_text(&api, msg.chat.id(), &text, None).await;
         }
     }
 
-    pub fn new(api: Api, users: Arc<Mutex<Users>>) -> QuizModule {
-        let db = Arc::new(Mutex::new(quests::Quester::new(env::var("QUESTER_DB").expect("QUESTER_DB not set")).unwrap()));
+    pub fn new(api: Api, users: Arc<Mutex<Users>>, tasks: &Tasks, config: Config, quester_db: &Path) -> QuizModule {
+        let db = Arc::new(Mutex::new(quests::Quester::new(quester_db).unwrap()));
 
+        let state = State::new(quester_db).unwrap();
+        // Rejected values stay in the legacy table, so they are not lost
+        for (chat, key, value) in state.legacy_settings().unwrap() {
+            let text = match (key.as_str(), value.as_str()) {
+                ("tidy", "1") => "on",
+                ("tidy", _) => "off",
+                _ => value.as_str(),
+            };
+            match config.set_text(ChatId::new(chat), &key, text) {
+                Ok(()) => state.remove_legacy_setting(chat, &key).unwrap(),
+                Err(e) => println!("Quiz setting {}={} of {} not migrated: {:?}", key, value, chat, e),
+            }
+        }
         let polls = Arc::new(Mutex::new(PollList {
             polls: HashMap::new(),
-            counts: HashMap::new()
+            counts: HashMap::new(),
+            chains: HashMap::new(),
+            trash: HashMap::new(),
+            state,
+            config,
         }));
 
-        tokio::spawn(QuizModule::timer_loop(api.clone(), db.clone(), polls.clone()));
+        tasks.spawn(QuizModule::timer_loop(api.clone(), db.clone(), polls.clone()));
 
         let (poll_send, poll_recv) = mpsc::channel::<telegram_bot::UpdateKind>(1024);
-        tokio::spawn(QuizModule::poll_loop(poll_recv, users.clone(), polls.clone()));
+        tasks.spawn(QuizModule::poll_loop(poll_recv, users.clone(), polls.clone()));
+
+        let (migrate_send, migrate_recv) = mpsc::channel::<Message>(1024);
+        tasks.spawn(QuizModule::migrate_loop(migrate_recv, polls.clone()));
+
+        let (command_send, command_recv) = mpsc::channel::<Invocation>(1024);
+        tasks.spawn(QuizModule::quiz_handler(command_recv, api.clone(), polls.clone(), db.clone(), users.clone()));
 
-        let (command_send, command_recv) = mpsc::channel::<Message>(1024);
-        tokio::spawn(QuizModule::quiz_handler(command_recv, api.clone(), polls.clone(), db.clone()));
+        let (hint_send, hint_recv) = mpsc::channel::<Invocation>(1024);
+        tasks.spawn(QuizModule::hint_handler(hint_recv, api.clone(), polls.clone(), users.clone()));
+
+        let (playlist_send, playlist_recv) = mpsc::channel::<Invocation>(1024);
+        tasks.spawn(QuizModule::playlist_handler(playlist_recv, api.clone(), db.clone()));
 
         QuizModule {
             command: command_send,
+            hint: hint_send,
+            playlist: playlist_send,
             poll: poll_send,
+            migrate: migrate_send,
         }
     }
+
+    /// Moves the stored quiz of a group to its supergroup, registered with `Dispatcher::add_migration`
+    pub fn migrate_chat(quester_db: &Path, from: ChatId, to: ChatId) -> Result<(), String> {
+        let mut state = State::new(quester_db).map_err(|e| e.to_string())?;
+        state.migrate_chat(i64::from(from), i64::from(to)).map_err(|e| format!("{:?}", e))
+    }
 }
 
 impl Subscriber for QuizModule {
@@ -249,9 +935,115 @@ impl Subscriber for QuizModule {
         map
     }
 
-    fn by_command(&self) -> HashMap<&str, Vec<Sender<Message>>, RandomState> {
+    fn by_message_kind(&self) -> HashMap<types::MessageKind, Vec<Sender<Message>>, RandomState> {
         let mut map = HashMap::new();
-        map.insert("/quiz", vec![self.command.clone()]);
+        map.insert(types::MessageKind::MigrateToChatId, vec![self.migrate.clone()]);
+        map.insert(types::MessageKind::MigrateFromChatId, vec![self.migrate.clone()]);
         map
     }
+
+    fn commands(&self) -> Vec<Command> {
+        vec![
+            CommandSpec::new("quiz", "quiz.description").handled_by(self.command.clone()),
+            CommandSpec::new("quiz playlist", "quiz.playlist_description")
+                .arg("playlist", ArgKind::Word).handled_by(self.command.clone()),
+            CommandSpec::new("quiz stop", "quiz.stop_description").handled_by(self.command.clone()),
+            CommandSpec::new("quiz pause", "quiz.pause_description").handled_by(self.command.clone()),
+            CommandSpec::new("quiz resume", "quiz.resume_description").handled_by(self.command.clone()),
+            CommandSpec::new("quiz tidy", "quiz.tidy_description").role(QUIZ_MASTER)
+                .arg("value", ArgKind::Choice(&["on", "off"])).handled_by(self.command.clone()),
+            CommandSpec::new("quiz readtime", "quiz.readtime_description").role(QUIZ_MASTER)
+                .optional("base", ArgKind::Integer).optional("per_char", ArgKind::Integer).handled_by(self.command.clone()),
+            CommandSpec::new("quiz scoring", "quiz.scoring_description").role(QUIZ_MASTER)
+                .arg("mode", ArgKind::Choice(&["strict", "partial"])).handled_by(self.command.clone()),
+            CommandSpec::new("quiz cheaters", "quiz.cheaters_description").role(MODERATOR).handled_by(self.command.clone()),
+            CommandSpec::new("hint", "quiz.hint_description").handled_by(self.hint.clone()),
+            CommandSpec::new("playlist", "playlist.description").role(QUIZ_MASTER).handled_by(self.playlist.clone()),
+            // Playlists are shared by all chats
+            CommandSpec::new("playlist new", "playlist.new_description").owner()
+                .arg("name", ArgKind::Word).optional("order", ArgKind::Choice(&["shuffle"])).handled_by(self.playlist.clone()),
+            CommandSpec::new("playlist add", "playlist.add_description").owner()
+                .arg("name", ArgKind::Word).arg("item", ArgKind::Text).handled_by(self.playlist.clone()),
+            CommandSpec::new("playlist show", "playlist.show_description").role(QUIZ_MASTER)
+                .arg("name", ArgKind::Word).handled_by(self.playlist.clone()),
+            CommandSpec::new("playlist delete", "playlist.delete_description").owner()
+                .arg("name", ArgKind::Word).handled_by(self.playlist.clone()),
+        ]
+    }
+
+    fn policy(&self) -> Policy {
+        Policy::PerChat
+    }
+
+    fn settings() -> Vec<Setting> {
+        vec![
+            Setting::bool("tidy", "quiz.settings.tidy", false),
+            Setting::choice("scoring", "quiz.settings.scoring", &["strict", "partial"], "strict"),
+            Setting::integer("readtime_base", "quiz.settings.readtime_base", 0, 0, 10000, 100),
+            Setting::integer("readtime_per_char", "quiz.settings.readtime_per_char", 0, 0, 1000, 5),
+            Setting::integer("round_seconds", "quiz.settings.round_seconds", 15, 5, 600, 5),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quiz::quests::QuestionKind;
+
+    fn quester(category: &str, count: usize) -> Quester {
+        let mut quests = Quester::new(":memory:").unwrap();
+        for i in 0..count {
+            quests.add_quest(&Question {
+                id: 0,
+                kind: QuestionKind::Single,
+                category: Some(category.to_string()),
+                text: format!("Question {}", i),
+                answers: vec![("yes".to_string(), true), ("no".to_string(), false)],
+                media: None,
+            }).unwrap();
+        }
+        quests
+    }
+
+    #[test]
+    fn chain_ends_without_questions() {
+        let mut chain = Chain::new(UserId::new(1), None);
+        assert!(chain.next_quest(&quester("music", 0)).is_none());
+    }
+
+    #[test]
+    fn shuffled_playlist_ends_when_categories_run_out() {
+        let quests = quester("music", 2);
+        let playlist = Playlist {
+            name: "friday".to_string(),
+            shuffle: true,
+            items: vec![PlaylistItem::Category { name: "music".to_string(), weight: 1 }, PlaylistItem::Question(42)],
+        };
+        let mut chain = Chain::new(UserId::new(1), Some(playlist));
+        let mut asked = vec!();
+        while let Some(quest) = chain.next_quest(&quests) {
+            asked.push(quest.id);
+            assert!(asked.len() <= 2, "asked {:?}", asked);
+        }
+        asked.sort();
+        assert_eq!(asked, vec![1, 2]);
+    }
+
+    #[test]
+    fn ordered_playlist_skips_empty_categories() {
+        let quests = quester("music", 1);
+        let playlist = Playlist {
+            name: "friday".to_string(),
+            shuffle: false,
+            items: vec![
+                PlaylistItem::Category { name: "films".to_string(), weight: 1 },
+                PlaylistItem::Category { name: "music".to_string(), weight: 1 },
+                PlaylistItem::Category { name: "music".to_string(), weight: 1 },
+            ],
+        };
+        let mut chain = Chain::new(UserId::new(1), Some(playlist));
+        assert_eq!(chain.next_quest(&quests).map(|q| q.id), Some(1));
+        assert!(chain.next_quest(&quests).is_none());
+    }
 }
@@ -4,6 +4,7 @@ use fallible_iterator::FallibleIterator;
 use rand::prelude::SliceRandom;
 use rusqlite::params;
 use rand::thread_rng;
+use crate::utils::has_column;
 
 #[derive(Debug)]
 pub enum QuesterError {
@@ -16,10 +17,108 @@ impl From<rusqlite::Error> for QuesterError {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaKind {
+    Photo,
+    Audio,
+    Video,
+}
+
+impl MediaKind {
+    pub fn parse(name: &str) -> Option<MediaKind> {
+        match name {
+            "photo" => Some(MediaKind::Photo),
+            "audio" => Some(MediaKind::Audio),
+            "video" => Some(MediaKind::Video),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MediaKind::Photo => "photo",
+            MediaKind::Audio => "audio",
+            MediaKind::Video => "video",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuestionKind {
+    /// Quiz poll with one correct answer
+    Single,
+    /// Regular poll where several answers may be chosen
+    Multiple,
+    /// Quiz poll with "true" and "false" options in fixed order
+    TrueFalse,
+}
+
+impl QuestionKind {
+    pub fn parse(name: &str) -> Option<QuestionKind> {
+        match name {
+            "single" => Some(QuestionKind::Single),
+            "multiple" => Some(QuestionKind::Multiple),
+            "truefalse" => Some(QuestionKind::TrueFalse),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuestionKind::Single => "single",
+            QuestionKind::Multiple => "multiple",
+            QuestionKind::TrueFalse => "truefalse",
+        }
+    }
+}
+
+/// Media attached to a question. `source` is either a telegram `file_id` or a local path.
+#[derive(Debug, Clone)]
+pub struct Media {
+    pub kind: MediaKind,
+    pub source: String,
+}
+
 #[derive(Debug)]
 pub struct Question {
+    pub id: i64,
+    pub kind: QuestionKind,
+    pub category: Option<String>,
     pub text: String,
     pub answers: Vec<(String, bool)>,
+    pub media: Option<Media>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PlaylistItem {
+    Question(i64),
+    /// Random question from the category, `weight` is used by shuffled playlists
+    Category { name: String, weight: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    pub name: String,
+    pub shuffle: bool,
+    pub items: Vec<PlaylistItem>,
+}
+
+impl Playlist {
+    /// Ordered playlists return items one by one, shuffled ones draw a weighted random item
+    /// skipping questions which were already asked and categories which ran out of questions
+    pub fn next_item(&self, position: usize, used: &[i64], exhausted: &[String]) -> Option<&PlaylistItem> {
+        if !self.shuffle {
+            return self.items.get(position)
+        }
+        let candidates = self.items.iter().filter(|i| match i {
+            PlaylistItem::Question(id) => !used.contains(id),
+            PlaylistItem::Category { name, .. } => !exhausted.contains(name),
+        }).collect::<Vec<&PlaylistItem>>();
+        candidates.choose_weighted(&mut thread_rng(), |i| match i {
+            PlaylistItem::Question(_) => 1,
+            PlaylistItem::Category { weight, .. } => *weight,
+        }).ok().cloned()
+    }
 }
 
 pub struct Quester {
@@ -28,33 +127,165 @@ pub struct Quester {
 
 impl Quester {
     pub fn new<T: AsRef<Path>>(path: T) -> Result<Quester, rusqlite::Error> {
+        let db = Connection::open(path)?;
+        db.execute("CREATE TABLE IF NOT EXISTS questions
+                        (id INTEGER PRIMARY KEY, question TEXT)", params![])?;
+        db.execute("CREATE TABLE IF NOT EXISTS answers
+                        (question_id INTEGER, answer TEXT, valid INTEGER)", params![])?;
+        if !has_column(&db, "questions", "media")? {
+            db.execute("ALTER TABLE questions ADD COLUMN media_type TEXT", params![])?;
+            db.execute("ALTER TABLE questions ADD COLUMN media TEXT", params![])?;
+        }
+        if !has_column(&db, "questions", "kind")? {
+            db.execute("ALTER TABLE questions ADD COLUMN kind TEXT DEFAULT 'single'", params![])?;
+        }
+        if !has_column(&db, "questions", "category")? {
+            db.execute("ALTER TABLE questions ADD COLUMN category TEXT", params![])?;
+        }
+        db.execute("CREATE TABLE IF NOT EXISTS playlists
+                        (name TEXT PRIMARY KEY, shuffle INTEGER)", params![])?;
+        db.execute("CREATE TABLE IF NOT EXISTS playlist_items
+                        (playlist TEXT, question_id INTEGER, category TEXT, weight INTEGER)", params![])?;
         Ok(Quester{
-            db: Connection::open(path)?,
+            db,
         })
     }
 
-    pub fn get_quest(&self) -> Result<Question, QuesterError> {
-        let mut select_quest = self.db.prepare(
-            "SELECT * FROM questions ORDER BY RANDOM() LIMIT 1;",
-        )?;
+    fn load_quest(&self, query: &str, args: &[&dyn rusqlite::ToSql]) -> Result<Option<Question>, QuesterError> {
+        let mut select_quest = self.db.prepare(query)?;
         let mut select_answers = self.db.prepare(
-            "SELECT answer,valid FROM answers WHERE question_id=?;",
+            "SELECT answer,valid FROM answers WHERE question_id=? ORDER BY rowid;",
         )?;
 
-        let mut test = select_quest.query(params![])?;
+        let mut test = select_quest.query(args)?;
         if let Some(res) = test.next()? {
-            let id = res.get::<usize, i32>(0)?;
+            let id = res.get::<usize, i64>(0)?;
             let quest = res.get::<usize, String>(1)?;
+            let media_type = res.get::<usize, Option<String>>(2)?;
+            let media = res.get::<usize, Option<String>>(3)?;
+            let kind = res.get::<usize, Option<String>>(4)?.as_deref()
+                .and_then(QuestionKind::parse)
+                .unwrap_or(QuestionKind::Single);
+            let category = res.get::<usize, Option<String>>(5)?;
             let mut answers = select_answers.query(params![id])?.map(|row| {
                 Ok((row.get::<usize, String>(0)?, row.get::<usize, i32>(1)? == 1))
             }).collect::<Vec<(String, bool)>>()?;
-            answers.shuffle(&mut thread_rng());
-            Ok(Question {
+            if kind != QuestionKind::TrueFalse {
+                answers.shuffle(&mut thread_rng());
+            }
+            Ok(Some(Question {
+                id,
+                kind,
+                category,
                 text: quest,
-                answers
-            })
+                answers,
+                media: match (media_type.as_deref().and_then(MediaKind::parse), media) {
+                    (Some(kind), Some(source)) => Some(Media { kind, source }),
+                    _ => None
+                }
+            }))
         } else {
-            unreachable!()
+            Ok(None)
+        }
+    }
+
+    /// Random question, `None` if there are none
+    pub fn get_quest(&self) -> Result<Option<Question>, QuesterError> {
+        self.load_quest(
+            "SELECT id, question, media_type, media, kind, category FROM questions ORDER BY RANDOM() LIMIT 1;",
+            params![],
+        )
+    }
+
+    pub fn quest(&self, id: i64) -> Result<Option<Question>, QuesterError> {
+        self.load_quest(
+            "SELECT id, question, media_type, media, kind, category FROM questions WHERE id = ?;",
+            params![id],
+        )
+    }
+
+    /// Random question from the category which is not in `exclude`
+    pub fn category_quest(&self, category: &str, exclude: &[i64]) -> Result<Option<Question>, QuesterError> {
+        // Long chains exclude more ids than a statement takes parameters, so they are filtered here
+        let mut select = self.db.prepare("SELECT id FROM questions WHERE category = ?")?;
+        let ids = select.query(params![category])?
+            .map(|row| row.get::<usize, i64>(0))
+            .filter(|id| Ok(!exclude.contains(id)))
+            .collect::<Vec<i64>>()?;
+        match ids.choose(&mut thread_rng()) {
+            Some(id) => self.quest(*id),
+            None => Ok(None)
         }
     }
-}
\ No newline at end of file
+
+    pub fn playlist(&self, name: &str) -> Result<Option<Playlist>, QuesterError> {
+        let mut select_playlist = self.db.prepare("SELECT shuffle FROM playlists WHERE name = ?")?;
+        let shuffle = match select_playlist.query(params![name])?.next()? {
+            Some(row) => row.get::<usize, i32>(0)? == 1,
+            None => return Ok(None)
+        };
+        let mut select_items = self.db.prepare(
+            "SELECT question_id, category, weight FROM playlist_items WHERE playlist = ? ORDER BY rowid")?;
+        let items = select_items.query(params![name])?.map(|row| {
+            Ok(match row.get::<usize, Option<String>>(1)? {
+                Some(category) => PlaylistItem::Category { name: category, weight: row.get(2)? },
+                None => PlaylistItem::Question(row.get(0)?),
+            })
+        }).collect::<Vec<PlaylistItem>>()?;
+        Ok(Some(Playlist {
+            name: name.to_string(),
+            shuffle,
+            items
+        }))
+    }
+
+    pub fn playlists(&self) -> Result<Vec<String>, QuesterError> {
+        let mut query = self.db.prepare("SELECT name FROM playlists ORDER BY name")?;
+        let res = query.query(params![])?.map(|row| row.get(0)).collect()?;
+        Ok(res)
+    }
+
+    /// `false` if a playlist with the name already exists
+    pub fn create_playlist(&mut self, name: &str, shuffle: bool) -> Result<bool, QuesterError> {
+        let created = self.db.execute("INSERT OR IGNORE INTO playlists (name, shuffle) VALUES(?, ?)",
+                                      params![name, shuffle as i32])?;
+        Ok(created > 0)
+    }
+
+    pub fn add_to_playlist(&mut self, name: &str, item: &PlaylistItem) -> Result<(), QuesterError> {
+        match item {
+            PlaylistItem::Question(id) => self.db.execute(
+                "INSERT INTO playlist_items (playlist, question_id, category, weight) VALUES(?, ?, NULL, 1)",
+                params![name, id])?,
+            PlaylistItem::Category { name: category, weight } => self.db.execute(
+                "INSERT INTO playlist_items (playlist, question_id, category, weight) VALUES(?, NULL, ?, ?)",
+                params![name, category, weight])?,
+        };
+        Ok(())
+    }
+
+    pub fn remove_playlist(&mut self, name: &str) -> Result<(), QuesterError> {
+        let tx = self.db.transaction()?;
+        tx.execute("DELETE FROM playlists WHERE name = ?", params![name])?;
+        tx.execute("DELETE FROM playlist_items WHERE playlist = ?", params![name])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn add_quest(&mut self, quest: &Question) -> Result<i64, QuesterError> {
+        let tx = self.db.transaction()?;
+        let (media_type, media) = match &quest.media {
+            Some(m) => (Some(m.kind.name()), Some(m.source.as_str())),
+            None => (None, None)
+        };
+        tx.execute("INSERT INTO questions (question, media_type, media, kind, category) VALUES(?, ?, ?, ?, ?)",
+                   params![&quest.text, media_type, media, quest.kind.name(), &quest.category])?;
+        let id = tx.last_insert_rowid();
+        for (answer, valid) in &quest.answers {
+            tx.execute("INSERT INTO answers (question_id, answer, valid) VALUES(?, ?, ?)",
+                       params![id, answer, *valid as i32])?;
+        }
+        tx.commit()?;
+        Ok(id)
+    }
+}
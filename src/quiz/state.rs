@@ -0,0 +1,265 @@
+use rusqlite::{Connection, Error};
+use std::path::Path;
+use fallible_iterator::FallibleIterator;
+use rusqlite::params;
+use crate::utils::has_column;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub enum StateError {
+    DBError(rusqlite::Error)
+}
+
+impl From<rusqlite::Error> for StateError {
+    fn from(e: Error) -> Self {
+        StateError::DBError(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct StoredChain {
+    pub chat_id: i64,
+    pub starter: i64,
+    pub paused: bool,
+    pub rounds: u32,
+    pub playlist: Option<String>,
+    pub position: usize,
+    pub used: Vec<i64>,
+    /// Scores of the players as (uid, name, score), saved with `add_chain_score`
+    pub scores: Vec<(i64, String, i64)>,
+}
+
+#[derive(Debug)]
+pub struct StoredAnswer {
+    pub uid: i64,
+    pub name: String,
+    pub username: Option<String>,
+    pub correct: bool,
+}
+
+#[derive(Debug)]
+pub struct StoredPoll {
+    pub id: String,
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub correct: Vec<i32>,
+    pub multiple: bool,
+    pub correct_text: String,
+    pub text_len: usize,
+    pub revealed: usize,
+    /// Unix timestamp of the poll creation
+    pub start: i64,
+    pub answers: Vec<StoredAnswer>,
+    pub hints: Vec<(i64, u32)>,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+/// Persistent copy of the running quizzes, used to restore them after restart
+pub struct State {
+    db: Connection,
+}
+
+impl State {
+    pub fn new<T: AsRef<Path>>(path: T) -> Result<State, rusqlite::Error> {
+        let db = Connection::open(path)?;
+        db.execute("CREATE TABLE IF NOT EXISTS active_polls
+                        (id TEXT PRIMARY KEY, chat_id INTEGER, message_id INTEGER,
+                        correct_answer INTEGER, correct_text TEXT, revealed INTEGER, start INTEGER)", params![])?;
+        if !has_column(&db, "active_polls", "text_len")? {
+            db.execute("ALTER TABLE active_polls ADD COLUMN text_len INTEGER DEFAULT 0", params![])?;
+        }
+        if !has_column(&db, "active_polls", "correct_options")? {
+            db.execute("ALTER TABLE active_polls ADD COLUMN correct_options TEXT DEFAULT ''", params![])?;
+            db.execute("ALTER TABLE active_polls ADD COLUMN multiple INTEGER DEFAULT 0", params![])?;
+        }
+        db.execute("CREATE TABLE IF NOT EXISTS poll_answers
+                        (poll_id TEXT, uid INTEGER, name TEXT, correct INTEGER)", params![])?;
+        if !has_column(&db, "poll_answers", "username")? {
+            db.execute("ALTER TABLE poll_answers ADD COLUMN username TEXT", params![])?;
+        }
+        db.execute("CREATE TABLE IF NOT EXISTS poll_hints
+                        (poll_id TEXT, uid INTEGER, hints INTEGER, PRIMARY KEY(poll_id, uid))", params![])?;
+        db.execute("CREATE TABLE IF NOT EXISTS settings
+                        (chat_id INTEGER, key TEXT, value TEXT, PRIMARY KEY(chat_id, key))", params![])?;
+        db.execute("CREATE TABLE IF NOT EXISTS chains
+                        (chat_id INTEGER PRIMARY KEY, count INTEGER)", params![])?;
+        if !has_column(&db, "chains", "starter")? {
+            db.execute("ALTER TABLE chains ADD COLUMN starter INTEGER DEFAULT 0", params![])?;
+            db.execute("ALTER TABLE chains ADD COLUMN paused INTEGER DEFAULT 0", params![])?;
+            db.execute("ALTER TABLE chains ADD COLUMN rounds INTEGER DEFAULT 0", params![])?;
+        }
+        if !has_column(&db, "chains", "playlist")? {
+            db.execute("ALTER TABLE chains ADD COLUMN playlist TEXT", params![])?;
+            db.execute("ALTER TABLE chains ADD COLUMN position INTEGER DEFAULT 0", params![])?;
+            db.execute("ALTER TABLE chains ADD COLUMN used TEXT DEFAULT ''", params![])?;
+        }
+        db.execute("CREATE TABLE IF NOT EXISTS chain_scores
+                        (chat_id INTEGER, uid INTEGER, name TEXT, score INTEGER, PRIMARY KEY(chat_id, uid))", params![])?;
+        Ok(State {
+            db,
+        })
+    }
+
+    pub fn add_poll(&mut self, poll: &StoredPoll) -> Result<(), StateError> {
+        self.db.execute("INSERT OR REPLACE INTO active_polls
+                        (id, chat_id, message_id, correct_answer, correct_text, revealed, start, text_len,
+                        correct_options, multiple)
+                        VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                        params![&poll.id, poll.chat_id, poll.message_id, poll.correct.first().cloned().unwrap_or(-1),
+                                &poll.correct_text, poll.revealed as i64, poll.start, poll.text_len as i64,
+                                poll.correct.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(","),
+                                poll.multiple as i32])?;
+        Ok(())
+    }
+
+    pub fn add_answer(&mut self, poll_id: &str, answer: &StoredAnswer) -> Result<(), StateError> {
+        self.db.execute("INSERT INTO poll_answers (poll_id, uid, name, correct, username) VALUES(?, ?, ?, ?, ?)",
+                        params![poll_id, answer.uid, &answer.name, answer.correct as i32, &answer.username])?;
+        Ok(())
+    }
+
+    pub fn set_hints(&mut self, poll_id: &str, uid: i64, hints: u32, revealed: usize) -> Result<(), StateError> {
+        let tx = self.db.transaction()?;
+        tx.execute("INSERT OR REPLACE INTO poll_hints (poll_id, uid, hints) VALUES(?, ?, ?)",
+                   params![poll_id, uid, hints])?;
+        tx.execute("UPDATE active_polls SET revealed = ? WHERE id = ?",
+                   params![revealed as i64, poll_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_poll(&mut self, poll_id: &str) -> Result<(), StateError> {
+        let tx = self.db.transaction()?;
+        tx.execute("DELETE FROM active_polls WHERE id = ?", params![poll_id])?;
+        tx.execute("DELETE FROM poll_answers WHERE poll_id = ?", params![poll_id])?;
+        tx.execute("DELETE FROM poll_hints WHERE poll_id = ?", params![poll_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn polls(&mut self) -> Result<Vec<StoredPoll>, StateError> {
+        let mut select_polls = self.db.prepare(
+            "SELECT id, chat_id, message_id, correct_answer, correct_text, revealed, start, text_len,
+                    correct_options, multiple FROM active_polls")?;
+        let mut select_answers = self.db.prepare(
+            "SELECT uid, name, correct, username FROM poll_answers WHERE poll_id = ? ORDER BY rowid")?;
+        let mut select_hints = self.db.prepare(
+            "SELECT uid, hints FROM poll_hints WHERE poll_id = ?")?;
+
+        let mut polls = select_polls.query(params![])?.map(|row| {
+            Ok(StoredPoll {
+                id: row.get(0)?,
+                chat_id: row.get(1)?,
+                message_id: row.get(2)?,
+                correct: {
+                    let options = row.get::<usize, String>(8)?;
+                    if options.is_empty() {
+                        vec![row.get(3)?]
+                    } else {
+                        options.split(',').filter_map(|o| o.parse().ok()).collect()
+                    }
+                },
+                multiple: row.get::<usize, i32>(9)? == 1,
+                correct_text: row.get(4)?,
+                revealed: row.get::<usize, i64>(5)? as usize,
+                start: row.get(6)?,
+                text_len: row.get::<usize, i64>(7)? as usize,
+                answers: vec!(),
+                hints: vec!(),
+            })
+        }).collect::<Vec<StoredPoll>>()?;
+
+        for poll in &mut polls {
+            poll.answers = select_answers.query(params![&poll.id])?.map(|row| {
+                Ok(StoredAnswer {
+                    uid: row.get(0)?,
+                    name: row.get(1)?,
+                    correct: row.get::<usize, i32>(2)? == 1,
+                    username: row.get(3)?,
+                })
+            }).collect()?;
+            poll.hints = select_hints.query(params![&poll.id])?.map(|row| {
+                Ok((row.get(0)?, row.get(1)?))
+            }).collect()?;
+        }
+        Ok(polls)
+    }
+
+    pub fn set_chain(&mut self, chain: &StoredChain) -> Result<(), StateError> {
+        self.db.execute("INSERT OR REPLACE INTO chains (chat_id, count, starter, paused, rounds, playlist, position, used)
+                        VALUES(?, 1, ?, ?, ?, ?, ?, ?)",
+                        params![chain.chat_id, chain.starter, chain.paused as i32, chain.rounds, &chain.playlist,
+                                chain.position as i64,
+                                chain.used.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(",")])?;
+        Ok(())
+    }
+
+    pub fn add_chain_score(&mut self, chat_id: i64, uid: i64, name: &str, delta: i64) -> Result<(), StateError> {
+        let tx = self.db.transaction()?;
+        tx.execute("INSERT OR IGNORE INTO chain_scores (chat_id, uid, name, score) VALUES(?, ?, ?, 0)",
+                   params![chat_id, uid, name])?;
+        tx.execute("UPDATE chain_scores SET name = ?, score = score + ? WHERE chat_id = ? AND uid = ?",
+                   params![name, delta, chat_id, uid])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_chain(&mut self, chat_id: i64) -> Result<(), StateError> {
+        let tx = self.db.transaction()?;
+        tx.execute("DELETE FROM chains WHERE chat_id = ?", params![chat_id])?;
+        tx.execute("DELETE FROM chain_scores WHERE chat_id = ?", params![chat_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Moves the running quiz of a group to its supergroup, a chain the supergroup already has is kept
+    pub fn migrate_chat(&mut self, from: i64, to: i64) -> Result<(), StateError> {
+        let tx = self.db.transaction()?;
+        tx.execute("UPDATE active_polls SET chat_id = ? WHERE chat_id = ?", params![to, from])?;
+        tx.execute("UPDATE OR IGNORE chains SET chat_id = ? WHERE chat_id = ?", params![to, from])?;
+        tx.execute("DELETE FROM chains WHERE chat_id = ?", params![from])?;
+        tx.execute("UPDATE OR IGNORE chain_scores SET chat_id = ? WHERE chat_id = ?", params![to, from])?;
+        tx.execute("DELETE FROM chain_scores WHERE chat_id = ?", params![from])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn chains(&mut self) -> Result<Vec<StoredChain>, StateError> {
+        let mut query = self.db.prepare("SELECT chat_id, starter, paused, rounds, playlist, position, used FROM chains WHERE count > 0")?;
+        let mut select_scores = self.db.prepare("SELECT uid, name, score FROM chain_scores WHERE chat_id = ?")?;
+        let mut res = query.query(params![])?.map(|row| {
+            Ok(StoredChain {
+                chat_id: row.get(0)?,
+                starter: row.get(1)?,
+                paused: row.get::<usize, i32>(2)? == 1,
+                rounds: row.get(3)?,
+                playlist: row.get(4)?,
+                position: row.get::<usize, i64>(5)? as usize,
+                used: row.get::<usize, String>(6)?.split(',').filter_map(|id| id.parse().ok()).collect(),
+                scores: vec!(),
+            })
+        }).collect::<Vec<StoredChain>>()?;
+        for chain in &mut res {
+            chain.scores = select_scores.query(params![chain.chat_id])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .collect()?;
+        }
+        Ok(res)
+    }
+
+    /// Settings stored before they moved to the module config
+    pub fn legacy_settings(&self) -> Result<Vec<(i64, String, String)>, StateError> {
+        let mut query = self.db.prepare("SELECT chat_id, key, value FROM settings")?;
+        let rows = query.query(params![])?.map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)));
+        Ok(rows.collect()?)
+    }
+
+    /// Forgets a legacy setting once the module config has accepted it
+    pub fn remove_legacy_setting(&self, chat: i64, key: &str) -> Result<(), StateError> {
+        self.db.execute("DELETE FROM settings WHERE chat_id=? AND key=?", params![chat, key])?;
+        Ok(())
+    }
+}
@@ -1 +1,21 @@
+use crate::quiz::quests::{Media, MediaKind};
+use crate::utils::must_send;
+use std::path::Path;
+use telegram_bot::{Api, ChatId, InputFile, InputFileRef, InputFileUpload, MessageOrChannelPost, SendAudio, SendPhoto, SendVideo};
 
+fn input_file(source: &str) -> InputFile {
+    if Path::new(source).exists() {
+        InputFileUpload::with_path(source).into()
+    } else {
+        InputFileRef::new(source).into()
+    }
+}
+
+pub(super) async fn send_media(api: &Api, chat: ChatId, media: &Media) -> Option<MessageOrChannelPost> {
+    let file = input_file(&media.source);
+    match media.kind {
+        MediaKind::Photo => must_send(api, SendPhoto::new(chat, file)).await,
+        MediaKind::Audio => must_send(api, SendAudio::new(chat, file)).await,
+        MediaKind::Video => must_send(api, SendVideo::new(chat, file)).await,
+    }
+}
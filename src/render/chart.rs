@@ -0,0 +1,121 @@
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_line_segment_mut, draw_text_mut};
+use imageproc::rect::Rect;
+use rusttype::{Font, Scale};
+use crate::render::{RenderError, BACKGROUND, FOREGROUND, MUTED, encode_png, text_width};
+
+pub struct Series {
+    pub name: String,
+    /// `(unix time, cumulative score)` ordered by time
+    pub points: Vec<(i64, i64)>,
+}
+
+const WIDTH: u32 = 900;
+const HEIGHT: u32 = 520;
+const LEFT: u32 = 80;
+const RIGHT: u32 = 30;
+const TOP: u32 = 70;
+const BOTTOM: u32 = 50;
+const GRID: Rgba<u8> = Rgba([58, 60, 76, 255]);
+
+const COLORS: [Rgba<u8>; 4] = [
+    Rgba([100, 181, 246, 255]),
+    Rgba([255, 183, 77, 255]),
+    Rgba([129, 199, 132, 255]),
+    Rgba([229, 115, 115, 255]),
+];
+
+pub const MAX_SERIES: usize = COLORS.len();
+
+/// `YYYY-MM-DD` of a unix timestamp
+fn date(ts: i64) -> String {
+    // Howard Hinnant's days_from_civil inverse
+    let z = ts.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Line chart of cumulative scores, one line per series, encoded as PNG
+pub fn chart(series: &[Series], font: &Font) -> Result<Vec<u8>, RenderError> {
+    let mut img = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+    let points = series.iter().flat_map(|s| s.points.iter());
+    let (mut t0, mut t1, mut v0, mut v1) = (i64::MAX, i64::MIN, 0, 0);
+    for (t, v) in points {
+        t0 = t0.min(*t);
+        t1 = t1.max(*t);
+        v0 = v0.min(*v);
+        v1 = v1.max(*v);
+    }
+    if t0 > t1 {
+        t0 = 0;
+        t1 = 1;
+    }
+    if t0 == t1 {
+        t1 = t0 + 1;
+    }
+    if v0 == v1 {
+        v1 = v0 + 1;
+    }
+
+    let (w, h) = ((WIDTH - LEFT - RIGHT) as f32, (HEIGHT - TOP - BOTTOM) as f32);
+    let x = |t: i64| LEFT as f32 + (t - t0) as f32 / (t1 - t0) as f32 * w;
+    let y = |v: i64| TOP as f32 + (v1 - v) as f32 / (v1 - v0) as f32 * h;
+
+    let label = Scale::uniform(20.0);
+    for v in [v0, 0, v1].iter() {
+        draw_line_segment_mut(&mut img, (LEFT as f32, y(*v)), ((WIDTH - RIGHT) as f32, y(*v)), GRID);
+        let text = v.to_string();
+        let tw = text_width(font, label, &text);
+        draw_text_mut(&mut img, MUTED, LEFT.saturating_sub(tw + 10), y(*v) as u32 - 10, label, font, &text);
+    }
+    draw_text_mut(&mut img, MUTED, LEFT, HEIGHT - BOTTOM + 12, label, font, &date(t0));
+    let end = date(t1);
+    draw_text_mut(&mut img, MUTED, WIDTH - RIGHT - text_width(font, label, &end), HEIGHT - BOTTOM + 12, label, font, &end);
+
+    let mut legend = LEFT;
+    for (s, color) in series.iter().zip(COLORS.iter()) {
+        draw_filled_rect_mut(&mut img, Rect::at(legend as i32, 26).of_size(16, 16), *color);
+        draw_text_mut(&mut img, FOREGROUND, legend + 24, 22, Scale::uniform(24.0), font, &s.name);
+        legend += 24 + text_width(font, Scale::uniform(24.0), &s.name) + 30;
+
+        let mut prev = (x(t0), y(0));
+        for (t, v) in &s.points {
+            let next = (x(*t), y(*v));
+            // Scores only change at ledger entries, so the line is a step function
+            for offset in 0..3 {
+                let o = offset as f32 - 1.0;
+                draw_line_segment_mut(&mut img, (prev.0, prev.1 + o), (next.0, prev.1 + o), *color);
+                draw_line_segment_mut(&mut img, (next.0 + o, prev.1), (next.0 + o, next.1), *color);
+            }
+            prev = next;
+        }
+        for offset in 0..3 {
+            let o = offset as f32 - 1.0;
+            draw_line_segment_mut(&mut img, (prev.0, prev.1 + o), ((WIDTH - RIGHT) as f32, prev.1 + o), *color);
+        }
+    }
+
+    encode_png(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::date;
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(86399), "1970-01-01");
+        assert_eq!(date(951782400), "2000-02-29");
+        assert_eq!(date(1609459199), "2020-12-31");
+        assert_eq!(date(1709210096), "2024-02-29");
+        assert_eq!(date(-1), "1969-12-31");
+    }
+}
@@ -0,0 +1,119 @@
+pub mod podium;
+pub mod chart;
+
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_text_mut};
+use rusttype::{point, Font, Scale};
+use telegram_bot::{Api, GetFile, GetUserProfilePhotos, UserId};
+use crate::utils::{download, must_send};
+
+#[derive(Debug)]
+pub enum RenderError {
+    IOError(std::io::Error),
+    ImageError(image::ImageError),
+    FontNotSet,
+    InvalidFont,
+    /// Rendering panicked
+    Crashed(String),
+}
+
+impl From<std::io::Error> for RenderError {
+    fn from(e: std::io::Error) -> Self {
+        RenderError::IOError(e)
+    }
+}
+
+impl From<image::ImageError> for RenderError {
+    fn from(e: image::ImageError) -> Self {
+        RenderError::ImageError(e)
+    }
+}
+
+pub(crate) const BACKGROUND: Rgba<u8> = Rgba([32, 34, 46, 255]);
+pub(crate) const FOREGROUND: Rgba<u8> = Rgba([236, 236, 240, 255]);
+pub(crate) const MUTED: Rgba<u8> = Rgba([150, 152, 170, 255]);
+
+/// Runs the rendering on a blocking thread, a panic in it is returned as an error
+pub async fn spawn<F: FnOnce() -> Result<Vec<u8>, RenderError> + Send + 'static>(render: F) -> Result<Vec<u8>, RenderError> {
+    tokio::task::spawn_blocking(render).await.unwrap_or_else(|e| Err(RenderError::Crashed(e.to_string())))
+}
+
+/// TrueType font from `FONT_PATH`
+pub fn load_font() -> Result<Font<'static>, RenderError> {
+    let path = std::env::var("FONT_PATH").map_err(|_| RenderError::FontNotSet)?;
+    let data = std::fs::read(path)?;
+    Font::try_from_vec(data).ok_or(RenderError::InvalidFont)
+}
+
+pub(crate) fn encode_png(img: RgbaImage) -> Result<Vec<u8>, RenderError> {
+    let mut res = vec!();
+    DynamicImage::ImageRgba8(img).write_to(&mut res, ImageOutputFormat::Png)?;
+    Ok(res)
+}
+
+pub(crate) fn text_width(font: &Font, scale: Scale, text: &str) -> u32 {
+    font.layout(text, scale, point(0.0, 0.0))
+        .filter_map(|g| g.pixel_bounding_box().map(|b| b.max.x))
+        .max()
+        .unwrap_or(0) as u32
+}
+
+/// Draws the text centered at `cx`, shortened with an ellipsis to fit into `max_width`
+pub(crate) fn draw_centered(img: &mut RgbaImage, color: Rgba<u8>, cx: u32, y: u32, scale: Scale, font: &Font, text: &str, max_width: u32) {
+    let mut text = text.to_string();
+    while text_width(font, scale, &text) > max_width && text.chars().count() > 1 {
+        let mut chars = text.trim_end_matches('…').chars().collect::<Vec<char>>();
+        chars.pop();
+        text = chars.into_iter().collect::<String>() + "…";
+    }
+    let width = text_width(font, scale, &text);
+    draw_text_mut(img, color, cx.saturating_sub(width / 2), y, scale, font, &text);
+}
+
+/// Stable color for users without an avatar
+pub(crate) fn user_color(uid: i64) -> Rgba<u8> {
+    const PALETTE: [[u8; 3]; 6] = [[229, 115, 115], [129, 199, 132], [100, 181, 246], [255, 183, 77], [186, 104, 200], [77, 208, 225]];
+    let c = PALETTE[(uid.rem_euclid(PALETTE.len() as i64)) as usize];
+    Rgba([c[0], c[1], c[2], 255])
+}
+
+/// Round avatar centered at `(cx, cy)`, initials on a colored circle if there is no photo
+pub(crate) fn draw_avatar(img: &mut RgbaImage, cx: u32, cy: u32, radius: u32, avatar: Option<&DynamicImage>,
+                          uid: i64, name: &str, font: &Font) {
+    match avatar {
+        Some(avatar) => {
+            let size = radius * 2;
+            let avatar = avatar.resize_to_fill(size, size, image::imageops::FilterType::Triangle).to_rgba8();
+            let r2 = (radius * radius) as i64;
+            for (x, y, pixel) in avatar.enumerate_pixels() {
+                let (dx, dy) = (x as i64 - radius as i64, y as i64 - radius as i64);
+                let (px, py) = (cx + x, cy + y);
+                if dx * dx + dy * dy <= r2 && px >= radius && py >= radius
+                    && px - radius < img.width() && py - radius < img.height() {
+                    img.put_pixel(px - radius, py - radius, *pixel);
+                }
+            }
+        }
+        None => {
+            draw_filled_circle_mut(img, (cx as i32, cy as i32), radius as i32, user_color(uid));
+            let initials = name.split_whitespace()
+                .filter_map(|w| w.chars().next())
+                .take(2)
+                .flat_map(char::to_uppercase)
+                .collect::<String>();
+            let scale = Scale::uniform(radius as f32);
+            draw_centered(img, FOREGROUND, cx, cy - radius / 2, scale, font, &initials, radius * 2);
+        }
+    }
+}
+
+/// Current profile photo of the user, `None` if there is none or it can't be downloaded
+pub async fn avatar(api: &Api, user: UserId) -> Option<DynamicImage> {
+    let mut request = GetUserProfilePhotos::new(user);
+    request.limit(1);
+    let photos = must_send(api, request).await?;
+    let photo = photos.photos.first()?.first()?;
+    let file = must_send(api, GetFile::new(photo)).await?;
+    let data = download(&file).await?;
+    image::load_from_memory(&data).ok()
+}
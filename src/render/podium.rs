@@ -0,0 +1,61 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut};
+use imageproc::rect::Rect;
+use rusttype::{Font, Scale};
+use crate::render::{RenderError, BACKGROUND, FOREGROUND, MUTED, draw_avatar, draw_centered, encode_png};
+
+pub struct Entry {
+    pub uid: i64,
+    pub name: String,
+    pub score: i64,
+    pub rank: String,
+    pub avatar: Option<DynamicImage>,
+}
+
+const WIDTH: u32 = 900;
+const PODIUM_HEIGHT: u32 = 560;
+const ROW_HEIGHT: u32 = 44;
+const COLUMN: u32 = WIDTH / 3;
+const AVATAR_RADIUS: u32 = 64;
+
+const PEDESTALS: [(u32, Rgba<u8>); 3] = [
+    (220, Rgba([255, 202, 40, 255])),
+    (160, Rgba([189, 189, 189, 255])),
+    (110, Rgba([205, 127, 50, 255])),
+];
+
+/// Podium for the first three entries and a list for the rest, encoded as PNG
+pub fn podium(entries: &[Entry], font: &Font) -> Result<Vec<u8>, RenderError> {
+    let rest = entries.len().saturating_sub(3) as u32;
+    let height = PODIUM_HEIGHT + rest * ROW_HEIGHT + if rest > 0 { 20 } else { 0 };
+    let mut img = RgbaImage::from_pixel(WIDTH, height, BACKGROUND);
+
+    // 2nd place on the left, 1st in the middle, 3rd on the right
+    for (place, column) in [(1usize, 0u32), (0, 1), (2, 2)].iter() {
+        let entry = match entries.get(*place) {
+            Some(entry) => entry,
+            None => continue
+        };
+        let (pedestal, color) = PEDESTALS[*place];
+        let cx = column * COLUMN + COLUMN / 2;
+        let top = PODIUM_HEIGHT - pedestal;
+        draw_filled_rect_mut(&mut img, Rect::at((column * COLUMN + 20) as i32, top as i32).of_size(COLUMN - 40, pedestal), color);
+        draw_centered(&mut img, BACKGROUND, cx, top + 10, Scale::uniform(72.0), font, &(place + 1).to_string(), COLUMN);
+
+        let avatar_y = top - 110 - AVATAR_RADIUS;
+        draw_avatar(&mut img, cx, avatar_y, AVATAR_RADIUS, entry.avatar.as_ref(), entry.uid, &entry.name, font);
+        draw_centered(&mut img, FOREGROUND, cx, avatar_y + AVATAR_RADIUS + 8, Scale::uniform(30.0), font, &entry.name, COLUMN - 20);
+        draw_centered(&mut img, MUTED, cx, avatar_y + AVATAR_RADIUS + 42, Scale::uniform(24.0), font,
+                      &format!("{} · {}", entry.score, entry.rank), COLUMN - 20);
+    }
+
+    for (i, entry) in entries.iter().skip(3).enumerate() {
+        let y = PODIUM_HEIGHT + 20 + i as u32 * ROW_HEIGHT;
+        let scale = Scale::uniform(28.0);
+        draw_text_mut(&mut img, MUTED, 40, y, scale, font, &format!("{}", i + 4));
+        draw_text_mut(&mut img, FOREGROUND, 100, y, scale, font, &entry.name);
+        draw_text_mut(&mut img, MUTED, WIDTH / 2 + 60, y, scale, font, &format!("{} · {}", entry.score, entry.rank));
+    }
+
+    encode_png(img)
+}
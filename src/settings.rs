@@ -0,0 +1,37 @@
+use crate::dispatcher::UpdateMode;
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+use telegram_bot::UserId;
+
+/// Configuration read from the environment once on start, tests fill it in directly
+pub(crate) struct Settings {
+    /// `DISPATCHER_DB`: enabled modules, languages, roles and module settings
+    pub dispatcher_db: PathBuf,
+    /// `QUESTER_DB`: questions, playlists and running quizzes
+    pub quester_db: PathBuf,
+    /// `USERS_DB`: players and their scores
+    pub users_db: PathBuf,
+    /// `BOT_OWNERS`, comma separated user ids
+    pub owners: HashSet<UserId>,
+    /// `RECORD_UPDATES`, file incoming updates are appended to
+    pub record_updates: Option<PathBuf>,
+    pub update_mode: UpdateMode,
+}
+
+impl Settings {
+    pub fn from_env() -> Settings {
+        Settings {
+            dispatcher_db: env::var("DISPATCHER_DB").expect("Dispatcher db path is not set").into(),
+            quester_db: env::var("QUESTER_DB").expect("QUESTER_DB not set").into(),
+            users_db: env::var("USERS_DB").expect("USERS_DB not set").into(),
+            owners: env::var("BOT_OWNERS").unwrap_or_default()
+                .split(',')
+                .filter_map(|id| id.trim().parse::<i64>().ok())
+                .map(UserId::new)
+                .collect(),
+            record_updates: env::var_os("RECORD_UPDATES").map(PathBuf::from),
+            update_mode: UpdateMode::from_env(),
+        }
+    }
+}
@@ -1,61 +1,288 @@
 mod ranks;
 
 use tokio::sync::mpsc::{Receiver, Sender};
-use telegram_bot::{Api, Message, SendMessage, ParseMode};
+use telegram_bot::{Api, UserId, ChatId, SendMessage, EditMessageText, CallbackQuery, MessageOrChannelPost,
+                   InlineKeyboardMarkup, InlineKeyboardButton, CanAnswerCallbackQuery, SendPhoto, InputFileUpload};
 use std::sync::Arc;
 use tokio::sync::{Mutex, mpsc};
 use tokio::stream::StreamExt;
-use crate::utils::must_send;
+use crate::utils::{must_send, send_text};
+use crate::render::{self, podium::Entry, chart::{self, Series}};
 use std::collections::HashMap;
-use crate::markdown;
+use crate::markdown::{self, MarkdownV2, Text};
 use crate::top::ranks::score_to_rank;
-use crate::users::Users;
-use crate::dispatcher::Subscriber;
-use std::collections::hash_map::RandomState;
+use crate::users::{Users, UsersError};
+use crate::dispatcher::{Policy, Subscriber, Tasks};
+use crate::dispatcher::commands::{ArgKind, Command, CommandSpec, Invocation, UserRef};
+use crate::dispatcher::types::UpdateKind;
+use crate::i18n::{self, Lang, tr, tr_text};
+
+const PAGE_SIZE: i64 = 10;
 
 pub struct UserTopModule {
-    top: Sender<Message>,
+    top: Sender<Invocation>,
+    podium: Sender<Invocation>,
+    chart: Sender<Invocation>,
+    navigation: Sender<telegram_bot::UpdateKind>,
 }
 
 impl UserTopModule {
-    pub fn new(api: Api, users: Arc<Mutex<Users>>) -> UserTopModule {
-        let (top_send, top_recv) = mpsc::channel::<Message>(1024);
-        tokio::spawn(UserTopModule::top_handler(top_recv, api.clone(), users.clone()));
+    pub fn new(api: Api, users: Arc<Mutex<Users>>, tasks: &Tasks) -> UserTopModule {
+        let (top_send, top_recv) = mpsc::channel::<Invocation>(1024);
+        let (nav_send, nav_recv) = mpsc::channel::<telegram_bot::UpdateKind>(1024);
+        let (podium_send, podium_recv) = mpsc::channel::<Invocation>(1024);
+        let (chart_send, chart_recv) = mpsc::channel::<Invocation>(1024);
+        tasks.spawn(UserTopModule::top_handler(top_recv, api.clone(), users.clone()));
+        tasks.spawn(UserTopModule::navigation_handler(nav_recv, api.clone(), users.clone()));
+        tasks.spawn(UserTopModule::podium_handler(podium_recv, api.clone(), users.clone()));
+        tasks.spawn(UserTopModule::chart_handler(chart_recv, api.clone(), users.clone()));
 
         UserTopModule {
             top: top_send,
+            podium: podium_send,
+            chart: chart_send,
+            navigation: nav_send,
+        }
+    }
+
+    /// Leaderboard page with the viewer's row highlighted and navigation buttons
+    async fn page(users: &Arc<Mutex<Users>>, chat: ChatId, page: i64, viewer: UserId) -> Result<(Text<MarkdownV2>, InlineKeyboardMarkup), UsersError> {
+        let (top, size) = {
+            let mut users = users.lock().await;
+            (users.get_top(page * PAGE_SIZE, PAGE_SIZE)?, users.top_size()?)
+        };
+        let pages = std::cmp::max((size + PAGE_SIZE - 1) / PAGE_SIZE, 1);
+
+        let lang = i18n::lang(chat);
+        let style = markdown::mentions(chat);
+        let mut data: Text<MarkdownV2> = tr_text(lang, "top.title", &[("page", (page + 1).into()), ("pages", pages.into())]);
+        for (pos, u) in top.iter().enumerate() {
+            let name = markdown::full_name(&u.0.first_name, &u.0.last_name);
+            let marker = if u.0.uid == i64::from(viewer) { "👉 " } else { "" };
+            data.text(&format!("\n{}{} ", marker, page * PAGE_SIZE + pos as i64 + 1))
+                .user(UserId::new(u.0.uid), &name, Some(&u.0.username), style)
+                .text(&format!(": {}, ", u.1))
+                .bold(&score_to_rank(lang, u.1));
+        }
+
+        let mut row = vec!();
+        if page > 0 {
+            row.push(InlineKeyboardButton::callback(tr(lang, "top.prev", &[]), format!("top:{}", page - 1)));
         }
+        row.push(InlineKeyboardButton::callback(tr(lang, "top.me", &[]), "top:me".to_string()));
+        if page + 1 < pages {
+            row.push(InlineKeyboardButton::callback(tr(lang, "top.next", &[]), format!("top:{}", page + 1)));
+        }
+        let mut keyboard = InlineKeyboardMarkup::new();
+        keyboard.add_row(row);
+        Ok((data, keyboard))
     }
 
-    async fn top_handler(mut events: Receiver<Message>, api: Api, users: Arc<Mutex<Users>>) {
-        while let Some(msg) = events.next().await {
-            let top = {
-                users.lock().await.get_top(20).unwrap()
+    async fn top_handler(mut events: Receiver<Invocation>, api: Api, users: Arc<Mutex<Users>>) {
+        while let Some(Invocation { message: msg, .. }) = events.next().await {
+            let chat = msg.chat.id();
+            let (data, keyboard) = match UserTopModule::page(&users, chat, 0, msg.from.id).await {
+                Ok(page) => page,
+                Err(e) => {
+                    println!("Top: failed to load the leaderboard: {:?}", e);
+                    must_send(&api, SendMessage::new(chat, tr(i18n::lang(chat), "top.failed", &[]))).await;
+                    continue
+                }
             };
+            let mut reply = SendMessage::new(msg.chat.id(), data.as_str().to_string());
+            reply.parse_mode(data.parse_mode());
+            reply.reply_markup(keyboard);
+            must_send(&api, reply).await;
+        }
+    }
 
-            let mut pos = 1;
-            let mut data = String::from("Top 10: ");
-            for u in top {
-                if u.1 < 0 {
-                    data += &format!("\n{} {}: \\{}, {}", pos, markdown::escape(&markdown::full_name(&u.0.first_name, &u.0.last_name)), u.1, markdown::bold(score_to_rank(u.1)));
-                } else {
-                    data += &format!("\n{} {}: {}, {}", pos, markdown::escape(&markdown::full_name(&u.0.first_name, &u.0.last_name)), u.1, markdown::bold(score_to_rank(u.1)));
+    /// Posts the first page of the leaderboard as a podium image, or as text if rendering fails
+    async fn podium_handler(mut events: Receiver<Invocation>, api: Api, users: Arc<Mutex<Users>>) {
+        while let Some(Invocation { message: msg, .. }) = events.next().await {
+            let chat = msg.chat.id();
+            let lang = i18n::lang(chat);
+            let top = users.lock().await.get_top(0, PAGE_SIZE);
+            let top = match top {
+                Ok(top) => top,
+                Err(e) => {
+                    println!("Podium: failed to load the leaderboard: {:?}", e);
+                    must_send(&api, SendMessage::new(chat, tr(lang, "top.failed", &[]))).await;
+                    continue
+                }
+            };
+            let mut entries = vec!();
+            for (pos, (user, score)) in top.into_iter().enumerate() {
+                let avatar = if pos < 3 { render::avatar(&api, UserId::new(user.uid)).await } else { None };
+                entries.push(Entry {
+                    uid: user.uid,
+                    name: markdown::full_name(&user.first_name, &user.last_name),
+                    score,
+                    rank: score_to_rank(lang, score),
+                    avatar
+                });
+            }
+            let image = render::spawn(move || {
+                render::load_font().and_then(|font| render::podium::podium(&entries, &font))
+            }).await;
+
+            match image {
+                Ok(png) => {
+                    must_send(&api, SendPhoto::new(chat, InputFileUpload::with_data(png, "podium.png"))).await;
+                }
+                Err(e) => {
+                    println!("Podium rendering failed: {:?}", e);
+                    match UserTopModule::page(&users, chat, 0, msg.from.id).await {
+                        Ok((data, _)) => {
+                            send_text(&api, chat, &data, None).await;
+                        }
+                        Err(e) => {
+                            println!("Podium: failed to load the leaderboard: {:?}", e);
+                            must_send(&api, SendMessage::new(chat, tr(lang, "top.failed", &[]))).await;
+                        }
+                    }
                 }
-                pos += 1;
             }
-            let c = msg.chat;
-            let mut msg = SendMessage::new(&c, &data);
-            msg.parse_mode(ParseMode::MarkdownV2);
-            must_send(&api, msg).await;
+        }
+    }
+
+    /// The requester followed by the mentioned users, `Err` with the reply if they can't be charted
+    async fn chart_users(users: &Arc<Mutex<Users>>, lang: Lang, requester: UserId, mentioned: Vec<&UserRef>) -> Result<Vec<i64>, String> {
+        let mut uids = vec![i64::from(requester)];
+        let mut users = users.lock().await;
+        for user in mentioned {
+            let uid = match user {
+                UserRef::Id(id) => i64::from(*id),
+                UserRef::Username(name) => match users.user_by_username(name) {
+                    Ok(Some(user)) => user.uid,
+                    Ok(None) => return Err(tr(lang, "chart.unknown_user", &[("name", name.into())])),
+                    Err(e) => {
+                        println!("Chart: failed to find @{}: {:?}", name, e);
+                        return Err(tr(lang, "chart.failed", &[]))
+                    }
+                }
+            };
+            if !uids.contains(&uid) {
+                uids.push(uid);
+            }
+        }
+        if uids.len() > chart::MAX_SERIES {
+            return Err(tr(lang, "chart.too_many", &[("n", (chart::MAX_SERIES as i64 - 1).into())]))
+        }
+        Ok(uids)
+    }
+
+    /// `/chart [@user ...]`: cumulative score of the requester compared with the mentioned users in this chat
+    async fn chart_handler(mut events: Receiver<Invocation>, api: Api, users: Arc<Mutex<Users>>) {
+        while let Some(invocation) = events.next().await {
+            let msg = &invocation.message;
+            let chat = msg.chat.id();
+            let lang = i18n::lang(chat);
+
+            let uids = match UserTopModule::chart_users(&users, lang, msg.from.id, invocation.users("user")).await {
+                Ok(uids) => uids,
+                Err(reply) => {
+                    must_send(&api, SendMessage::new(chat, reply)).await;
+                    continue
+                }
+            };
+            let series = {
+                let mut users = users.lock().await;
+                uids.iter().map(|uid| {
+                    let name = users.user(*uid)?
+                        .map(|u| markdown::full_name(&u.first_name, &u.last_name))
+                        .unwrap_or_else(|| uid.to_string());
+                    Ok(Series { name, points: users.score_history(i64::from(chat), *uid)? })
+                }).collect::<Result<Vec<Series>, UsersError>>()
+            };
+            let series = match series {
+                Ok(series) => series,
+                Err(e) => {
+                    println!("Chart: failed to load the history: {:?}", e);
+                    must_send(&api, SendMessage::new(chat, tr(lang, "chart.failed", &[]))).await;
+                    continue
+                }
+            };
+            if series.iter().all(|s| s.points.is_empty()) {
+                must_send(&api, SendMessage::new(chat, tr(lang, "chart.empty", &[]))).await;
+                continue
+            }
+
+            let image = render::spawn(move || {
+                render::load_font().and_then(|font| chart::chart(&series, &font))
+            }).await;
+            match image {
+                Ok(png) => {
+                    must_send(&api, SendPhoto::new(chat, InputFileUpload::with_data(png, "chart.png"))).await;
+                }
+                Err(e) => {
+                    println!("Chart rendering failed: {:?}", e);
+                    must_send(&api, SendMessage::new(chat, tr(lang, "chart.failed", &[]))).await;
+                }
+            }
+        }
+    }
+
+    /// Handles `top:<page>` and `top:me` buttons by editing the leaderboard in place
+    async fn navigation_handler(mut events: Receiver<telegram_bot::UpdateKind>, api: Api, users: Arc<Mutex<Users>>) {
+        while let Some(update) = events.next().await {
+            let query = match update {
+                telegram_bot::UpdateKind::CallbackQuery(query) => query,
+                _ => continue
+            };
+            let (msg, page) = match &query {
+                CallbackQuery { message: Some(MessageOrChannelPost::Message(msg)), data: Some(data), .. } => {
+                    let page = match data.strip_prefix("top:") {
+                        Some("me") => users.lock().await.top_position(i64::from(query.from.id))
+                            .map(|pos| pos.map(|pos| pos / PAGE_SIZE)),
+                        Some(page) => Ok(page.parse::<i64>().ok()),
+                        None => continue
+                    };
+                    (msg, page)
+                }
+                _ => continue
+            };
+            let page = match page {
+                Ok(Some(page)) if page >= 0 => UserTopModule::page(&users, msg.chat.id(), page, query.from.id).await,
+                Ok(_) => {
+                    must_send(&api, query.acknowledge()).await;
+                    continue
+                }
+                Err(e) => Err(e),
+            };
+            let (data, keyboard) = match page {
+                Ok(page) => page,
+                Err(e) => {
+                    println!("Top: failed to load the leaderboard: {:?}", e);
+                    must_send(&api, query.answer(tr(i18n::lang(msg.chat.id()), "top.failed", &[]))).await;
+                    continue
+                }
+            };
+            must_send(&api, query.acknowledge()).await;
+
+            let mut edit = EditMessageText::new(msg.chat.id(), msg.id, data.as_str().to_string());
+            edit.parse_mode(data.parse_mode());
+            edit.reply_markup(keyboard);
+            must_send(&api, edit).await;
         }
     }
 }
 
 impl Subscriber for UserTopModule {
-    fn by_command(&self) -> HashMap<&str, Vec<Sender<Message>>, RandomState> {
+    fn commands(&self) -> Vec<Command> {
+        vec![
+            CommandSpec::new("top", "top.description").handled_by(self.top.clone()),
+            CommandSpec::new("podium", "top.podium_description").handled_by(self.podium.clone()),
+            CommandSpec::new("chart", "chart.description").many("user", ArgKind::User).handled_by(self.chart.clone()),
+        ]
+    }
+
+    fn by_update_kind(&self) -> HashMap<UpdateKind, Vec<Sender<telegram_bot::UpdateKind>>> {
         let mut map = HashMap::new();
-        map.insert("/top", vec![self.top.clone()]);
+        map.insert(UpdateKind::CallbackQuery, vec![self.navigation.clone()]);
         map
     }
-}
 
+    fn policy(&self) -> Policy {
+        Policy::DropNewest
+    }
+}
@@ -2,6 +2,8 @@ use rusqlite::{Connection, Error};
 use std::path::Path;
 use fallible_iterator::FallibleIterator;
 use rusqlite::params;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::utils::has_column;
 
 #[derive(Debug)]
 pub enum UsersError {
@@ -22,6 +24,36 @@ pub struct User {
     pub username: String
 }
 
+/// Single entry of the answer history
+#[derive(Debug)]
+pub struct Answer<'a> {
+    pub chat_id: i64,
+    pub uid: i64,
+    pub poll_id: &'a str,
+    pub delta: i64,
+    pub correct: bool,
+    pub hints: u32,
+    /// Milliseconds between the poll start and the answer
+    pub latency: i64,
+    /// Answer was faster than the minimum reading time
+    pub flagged: bool,
+}
+
+/// Per user answer statistics in a chat
+#[derive(Debug)]
+pub struct AnswerStats {
+    pub user: User,
+    pub answers: i64,
+    pub correct: i64,
+    pub flagged: i64,
+    pub avg_correct_latency: i64,
+    pub min_latency: i64,
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
 pub struct Users {
     db: Connection,
 }
@@ -35,6 +67,14 @@ impl Users {
         db.execute("CREATE TABLE IF NOT EXISTS scores
                         (uid INTEGER UNIQUE PRIMARY KEY, score INTEGER)", params![])?;
         db.execute("CREATE UNIQUE INDEX IF NOT EXISTS id_index ON scores (uid)", params![])?;
+        db.execute("CREATE TABLE IF NOT EXISTS history
+                        (chat_id INTEGER, uid INTEGER, poll_id TEXT, kind TEXT,
+                        delta INTEGER, correct INTEGER, hints INTEGER, date INTEGER)", params![])?;
+        db.execute("CREATE INDEX IF NOT EXISTS history_index ON history (chat_id, uid)", params![])?;
+        if !has_column(&db, "history", "latency")? {
+            db.execute("ALTER TABLE history ADD COLUMN latency INTEGER DEFAULT 0", params![])?;
+            db.execute("ALTER TABLE history ADD COLUMN flagged INTEGER DEFAULT 0", params![])?;
+        }
         Ok(Users{
             db,
         })
@@ -53,12 +93,13 @@ impl Users {
         }
     }
 
-    pub fn get_top(&mut self, count: i64) -> Result<Vec<(User, i64)>, UsersError> {
+    pub fn get_top(&mut self, offset: i64, count: i64) -> Result<Vec<(User, i64)>, UsersError> {
         let mut select_users = self.db.prepare(
-            "SELECT users.uid, users.first_name, users.last_name, users.username, score FROM scores JOIN users on users.uid = scores.uid ORDER BY score DESC LIMIT ?",
+            "SELECT users.uid, users.first_name, users.last_name, users.username, score FROM scores JOIN users on users.uid = scores.uid
+                     ORDER BY score DESC, scores.uid LIMIT ? OFFSET ?",
         )?;
 
-        let users = select_users.query(params![count])?.map(|row| {
+        let users = select_users.query(params![count, offset])?.map(|row| {
             let user = User {
                 uid: row.get::<usize, i64>(0).unwrap(),
                 first_name: row.get::<usize, String>(1).unwrap(),
@@ -71,6 +112,65 @@ impl Users {
         Ok(users)
     }
 
+    /// Number of users in the top
+    pub fn top_size(&mut self) -> Result<i64, UsersError> {
+        Ok(self.db.query_row(
+            "SELECT COUNT(*) FROM scores JOIN users on users.uid = scores.uid", params![], |row| row.get(0))?)
+    }
+
+    /// Zero based position of the user in the top
+    pub fn top_position(&mut self, uid: i64) -> Result<Option<i64>, UsersError> {
+        let mut select = self.db.prepare(
+            "SELECT COUNT(*) FROM scores JOIN users on users.uid = scores.uid, scores AS own
+                     WHERE own.uid = ? AND (scores.score > own.score OR (scores.score = own.score AND scores.uid < own.uid))",
+        )?;
+        let mut exists = self.db.prepare("SELECT 1 FROM scores JOIN users on users.uid = scores.uid WHERE scores.uid = ?")?;
+        if !exists.exists(params![uid])? {
+            return Ok(None)
+        }
+        Ok(Some(select.query_row(params![uid], |row| row.get(0))?))
+    }
+
+    pub fn user(&mut self, uid: i64) -> Result<Option<User>, UsersError> {
+        self.find_user("SELECT uid, first_name, last_name, username FROM users WHERE uid = ?", &uid)
+    }
+
+    pub fn user_by_username(&mut self, username: &str) -> Result<Option<User>, UsersError> {
+        self.find_user("SELECT uid, first_name, last_name, username FROM users WHERE username = ? COLLATE NOCASE", &username)
+    }
+
+    fn find_user(&mut self, query: &str, arg: &dyn rusqlite::ToSql) -> Result<Option<User>, UsersError> {
+        let mut select = self.db.prepare(query)?;
+        let mut rows = select.query(params![arg])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(User {
+                uid: row.get(0)?,
+                first_name: row.get(1)?,
+                last_name: row.get::<usize, Option<String>>(2)?.unwrap_or_default(),
+                username: row.get::<usize, Option<String>>(3)?.unwrap_or_default(),
+            })),
+            None => Ok(None)
+        }
+    }
+
+    /// Cumulative score of the user in the chat after every ledger entry, as `(date, score)`
+    pub fn score_history(&mut self, chat_id: i64, uid: i64) -> Result<Vec<(i64, i64)>, UsersError> {
+        let mut select = self.db.prepare(
+            "SELECT date, delta FROM history WHERE chat_id = ? AND uid = ? ORDER BY date, rowid")?;
+        let mut score = 0;
+        let res = select.query(params![chat_id, uid])?.map(|row| {
+            score += row.get::<usize, i64>(1)?;
+            Ok((row.get::<usize, i64>(0)?, score))
+        }).collect()?;
+        Ok(res)
+    }
+
+    /// Moves the score history of a group to its supergroup
+    pub fn migrate_chat(&mut self, from: i64, to: i64) -> Result<(), UsersError> {
+        self.db.execute("UPDATE history SET chat_id = ? WHERE chat_id = ?", params![to, from])?;
+        Ok(())
+    }
+
     pub fn update_user(&mut self, user: &User) -> Result<(), UsersError> {
         let mut update = self.db.prepare(
             "INSERT INTO users (uid, first_name, last_name, username)
@@ -94,4 +194,64 @@ impl Users {
         select_quest.execute(params![uid, rating, rating])?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn add_history(&mut self, kind: &str, answer: &Answer) -> Result<(), UsersError> {
+        let tx = self.db.transaction()?;
+        tx.execute("INSERT INTO history (chat_id, uid, poll_id, kind, delta, correct, hints, date, latency, flagged)
+                     VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                   params![answer.chat_id, answer.uid, answer.poll_id, kind, answer.delta,
+                           answer.correct as i32, answer.hints, now(), answer.latency, answer.flagged as i32])?;
+        tx.execute("INSERT INTO scores (uid, score)
+                     VALUES(?, ?)
+                     ON CONFLICT(uid)
+                     DO UPDATE SET score = score + ?",
+                   params![answer.uid, answer.delta, answer.delta])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn add_answer(&mut self, answer: &Answer) -> Result<(), UsersError> {
+        self.add_history("answer", answer)
+    }
+
+    pub fn buy_hint(&mut self, chat_id: i64, uid: i64, poll_id: &str, cost: i64) -> Result<(), UsersError> {
+        self.add_history("hint", &Answer {
+            chat_id,
+            uid,
+            poll_id,
+            delta: -cost,
+            correct: false,
+            hints: 1,
+            latency: 0,
+            flagged: false
+        })
+    }
+
+    pub fn answer_stats(&mut self, chat_id: i64) -> Result<Vec<AnswerStats>, UsersError> {
+        let mut select = self.db.prepare(
+            "SELECT users.uid, users.first_name, users.last_name, users.username,
+                    COUNT(*), SUM(correct), SUM(flagged),
+                    IFNULL(AVG(CASE WHEN correct = 1 THEN latency END), 0), MIN(latency)
+             FROM history JOIN users ON users.uid = history.uid
+             WHERE chat_id = ? AND kind = 'answer'
+             GROUP BY users.uid",
+        )?;
+
+        let stats = select.query(params![chat_id])?.map(|row| {
+            Ok(AnswerStats {
+                user: User {
+                    uid: row.get(0)?,
+                    first_name: row.get(1)?,
+                    last_name: row.get(2)?,
+                    username: row.get(3)?,
+                },
+                answers: row.get(4)?,
+                correct: row.get(5)?,
+                flagged: row.get(6)?,
+                avg_correct_latency: row.get::<usize, f64>(7)? as i64,
+                min_latency: row.get(8)?,
+            })
+        }).collect::<Vec<AnswerStats>>()?;
+        Ok(stats)
+    }
+}
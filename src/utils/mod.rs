@@ -1,4 +1,6 @@
-use telegram_bot::{Request, Api, ResponseType, UpdateKind, Message, MessageChat, ChatId, ChannelPost};
+use telegram_bot::{Request, Api, ResponseType, UpdateKind, Message, MessageChat, ChatId, ChannelPost,
+                   SendMessage, MessageId, MessageOrChannelPost, File};
+use crate::markdown::{Format, Text, MESSAGE_LIMIT};
 use tokio::time::Duration;
 
 pub async fn must_send<Req: Request>(api: &Api, req: Req) -> Option<<Req::Response as ResponseType>::Type> {
@@ -20,3 +22,38 @@ pub async fn must_send<Req: Request>(api: &Api, req: Req) -> Option<<Req::Respon
     }
 }
 
+/// Sends the text split into as many messages as needed, the first one replies to `reply_to`
+pub async fn send_text<F: Format>(api: &Api, chat: ChatId, text: &Text<F>, reply_to: Option<MessageId>) -> Vec<MessageId> {
+    let mut ids = vec!();
+    for part in text.split(MESSAGE_LIMIT) {
+        let mut msg = SendMessage::new(chat, part);
+        msg.parse_mode(text.parse_mode());
+        if let (Some(reply_to), true) = (reply_to, ids.is_empty()) {
+            msg.reply_to(reply_to);
+        }
+        if let Some(MessageOrChannelPost::Message(m)) = must_send(api, msg).await {
+            ids.push(m.id);
+        }
+    }
+    ids
+}
+
+/// Downloads a file returned by `GetFile` from the Bot API server the `Api` uses, `TELEGRAM_API_URL` if set
+pub async fn download(file: &File) -> Option<Vec<u8>> {
+    let token = std::env::var("TELEGRAM_BOT_TOKEN").ok()?;
+    let base = std::env::var("TELEGRAM_API_URL").unwrap_or_else(|_| "https://api.telegram.org/".to_string());
+    let url = format!("{}/file/bot{}/{}", base.trim_end_matches('/'), token, file.file_path.as_ref()?);
+    let res = reqwest::get(&url).await.ok()?.error_for_status().ok()?;
+    Some(res.bytes().await.ok()?.to_vec())
+}
+
+pub fn has_column(db: &rusqlite::Connection, table: &str, column: &str) -> Result<bool, rusqlite::Error> {
+    let mut info = db.prepare(&format!("PRAGMA table_info({})", table))?;
+    let mut rows = info.query(rusqlite::params![])?;
+    while let Some(row) = rows.next()? {
+        if row.get::<usize, String>(1)? == column {
+            return Ok(true)
+        }
+    }
+    Ok(false)
+}